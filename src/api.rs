@@ -1,13 +1,476 @@
+use crate::cache::AnswerCache;
+use crate::shell::shell_words;
 use anyhow::Result;
 use console::{style, Color};
 use futures_util::StreamExt;
 use reqwest::Client;
+use serde::Deserialize;
 use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+pub const OPENROUTER_MODELS_URL: &str = "https://openrouter.ai/api/v1/models";
+pub const OPENROUTER_KEY_URL: &str = "https://openrouter.ai/api/v1/auth/key";
+pub const DEFAULT_TIMEOUT_SECS: u64 = 60;
+/// The context limit lookup happens at startup, so it gives up quickly
+const CONTEXT_LIMITS_TIMEOUT: Duration = Duration::from_secs(3);
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 1;
+/// Sent with every request unless `user_agent` is configured
+pub const DEFAULT_USER_AGENT: &str = concat!("shy/", env!("CARGO_PKG_VERSION"));
+/// Most stop sequences OpenAI-compatible providers accept in one request
+pub const MAX_STOP_SEQUENCES: usize = 4;
+/// Finish reason recorded when the user stops a response with Ctrl-C
+const CANCELLED: &str = "cancelled";
+/// How far back the spinner's tokens-per-second readout looks
+const TOKEN_RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// What went wrong with a request, as far as OpenRouter's error body tells us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorKind {
+    InvalidApiKey,
+    InsufficientCredits,
+    ModelNotFound,
+    RateLimited,
+    ProviderUnavailable,
+    Other,
+}
+
+/// A failed API request, with OpenRouter's message and a hint on how to fix it
+#[derive(Debug)]
+pub struct ApiError {
+    pub kind: ApiErrorKind,
+    pub status: u16,
+    pub message: String,
+}
+
+impl ApiError {
+    /// Parse `{"error": {"message": ..., "code": ...}}`, falling back to the raw body
+    pub fn from_response(status: u16, body: &str) -> Self {
+        let parsed: Option<Value> = serde_json::from_str(body).ok();
+        let error = parsed.as_ref().map(|json| &json["error"]);
+        let message = error
+            .and_then(|e| e["message"].as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| body.trim().to_string());
+        let code = error
+            .and_then(|e| e["code"].as_u64())
+            .map_or(status, |code| code as u16);
+
+        let lower = message.to_lowercase();
+        let kind = match code {
+            401 | 403 => ApiErrorKind::InvalidApiKey,
+            402 => ApiErrorKind::InsufficientCredits,
+            429 => ApiErrorKind::RateLimited,
+            404 => ApiErrorKind::ModelNotFound,
+            400 if lower.contains("model") && lower.contains("valid") => {
+                ApiErrorKind::ModelNotFound
+            }
+            500..=599 => ApiErrorKind::ProviderUnavailable,
+            _ => ApiErrorKind::Other,
+        };
+
+        Self {
+            kind,
+            status,
+            message,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self.kind {
+            ApiErrorKind::InvalidApiKey => "Invalid API key",
+            ApiErrorKind::InsufficientCredits => "Insufficient credits",
+            ApiErrorKind::ModelNotFound => "Model not found",
+            ApiErrorKind::RateLimited => "Rate limited",
+            ApiErrorKind::ProviderUnavailable => "Provider unavailable",
+            ApiErrorKind::Other => "API request failed",
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self.kind {
+            ApiErrorKind::InvalidApiKey => {
+                Some("Run `shy init` to enter a new key from https://openrouter.ai/keys")
+            }
+            ApiErrorKind::InsufficientCredits => {
+                Some("Add credits at https://openrouter.ai/credits or pick a free model with /model")
+            }
+            ApiErrorKind::ModelNotFound => Some("Pick another model with /model"),
+            ApiErrorKind::RateLimited => {
+                Some("Wait a moment and try again, or lower max_concurrent_requests")
+            }
+            ApiErrorKind::ProviderUnavailable => {
+                Some("Try again shortly or switch models with /model")
+            }
+            ApiErrorKind::Other => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.title(), self.status, self.message)?;
+        if let Some(hint) = self.hint() {
+            write!(f, "\n  Hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Reassembles server-sent events from `bytes_stream` chunks, which can end anywhere:
+/// in the middle of a line, a JSON payload or a multi-byte character
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    /// Feed one chunk; returns the `data` of every event it completed
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // A blank line ends the event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+            // Comments (`: OPENROUTER PROCESSING`) and other fields are ignored
+        }
+        events
+    }
+
+    /// The last event, if the stream closed without the blank line that ends it
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = String::from_utf8_lossy(&rest);
+        if let Some(data) = rest.trim_end().strip_prefix("data:") {
+            self.data.push(data.trim_start().to_string());
+        }
+        (!self.data.is_empty()).then(|| std::mem::take(&mut self.data).join("\n"))
+    }
+}
+
+/// Streaming speed for the spinner: a rate over the last few seconds, so a stalled
+/// stream shows up as a falling number, and an average over the whole response
+#[derive(Debug)]
+pub struct TokenRate {
+    window: Duration,
+    arrivals: VecDeque<(Instant, usize)>,
+    first: Option<Instant>,
+    total: usize,
+}
+
+impl TokenRate {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            arrivals: VecDeque::new(),
+            first: None,
+            total: 0,
+        }
+    }
+
+    /// Rough token count of a streamed delta: about four characters per token
+    pub fn estimate_tokens(content: &str) -> usize {
+        content.chars().count().div_ceil(4).max(1)
+    }
+
+    pub fn record(&mut self, now: Instant, tokens: usize) {
+        self.first.get_or_insert(now);
+        self.total += tokens;
+        self.arrivals.push_back((now, tokens));
+    }
+
+    /// Tokens per second over the sliding window; `None` before anything arrived
+    pub fn per_second(&mut self, now: Instant) -> Option<f32> {
+        while self
+            .arrivals
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+        {
+            self.arrivals.pop_front();
+        }
+        let first = self.first?;
+        let tokens: usize = self.arrivals.iter().map(|(_, tokens)| tokens).sum();
+        // Until a full window has passed, divide by the time since the first token
+        let span = now.duration_since(first).min(self.window);
+        Some(tokens as f32 / span.as_secs_f32().max(0.25))
+    }
+
+    /// Tokens per second from the first token to `now`
+    pub fn average(&self, now: Instant) -> Option<f32> {
+        let span = now.duration_since(self.first?).as_secs_f32();
+        Some(self.total as f32 / span.max(0.25))
+    }
+}
+
+/// Suggestions picked out of a response while it streams, one finished numbered line at a
+/// time, so they can be shown before the answer is complete. The full extraction once the
+/// response is in still decides what the menu offers
+#[derive(Debug, Default)]
+pub struct CommandScanner {
+    /// Bytes of the response already looked at, always at a line start
+    scanned: usize,
+    commands: Vec<String>,
+}
+
+impl CommandScanner {
+    /// Look at the lines of `response` completed since the last call
+    pub fn scan(&mut self, response: &str) {
+        let Some(end) = response[self.scanned..].rfind('\n').map(|i| self.scanned + i + 1) else {
+            return;
+        };
+        for line in response[self.scanned..end].lines() {
+            let line = line.trim();
+            let numbered = line
+                .split_once(". ")
+                .is_some_and(|(number, _)| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()));
+            if !numbered {
+                continue;
+            }
+            for command in crate::repl::extract_commands(line, 1) {
+                if !self.commands.contains(&command) {
+                    self.commands.push(command);
+                }
+            }
+        }
+        self.scanned = end;
+    }
+
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+}
 
 pub struct OpenRouterClient {
     client: Client,
     api_key: String,
     model: String,
+    api_url: String,
+    timeout: Duration,
+    quiet: bool,
+    markdown: bool,
+    /// Limits chat completions in flight, so bursts don't hit rate limits
+    request_slots: Arc<Semaphore>,
+    finish_reason: std::sync::Mutex<Option<String>>,
+    /// Generation ends at the first of these, both at the provider and while streaming
+    stop: Vec<String>,
+    /// Tried in order when `model` is unavailable, sent as OpenRouter's `models` list
+    fallback_models: Vec<String>,
+    /// The model that actually answered the last request, from the streamed chunks
+    served_model: std::sync::Mutex<Option<String>>,
+    /// Speed of the response being streamed, read by the spinner
+    token_rate: std::sync::Mutex<TokenRate>,
+    /// Suggestions finished so far in the response being streamed, shown under the spinner
+    early_commands: std::sync::Mutex<CommandScanner>,
+    user_agent: String,
+    /// Earlier answers to identical requests, when `cache_answers` is on
+    cache: Option<AnswerCache>,
+    /// Set by `/nocache` to ask the API even if a cached answer exists
+    skip_cache: AtomicBool,
+    /// Whether the last response came from the cache
+    cache_hit: AtomicBool,
+    /// Ask for suggestions as JSON through `response_format`; turned off for the rest
+    /// of the run when the provider rejects it
+    structured: AtomicBool,
+    /// Commands from the last structured answer, when it parsed
+    structured_commands: std::sync::Mutex<Option<Vec<String>>>,
+}
+
+/// Color a shell command: programs in cyan, flags in yellow, other arguments in white.
+/// Words come from `shell_words`, so `grep "foo bar" file` keeps its quoted argument
+/// whole, and the word after a `|` is colored as a program again
+pub fn highlight_command(command: &str) -> String {
+    let mut result = String::new();
+    let mut command_position = true;
+
+    for word in shell_words(command) {
+        if !result.is_empty() {
+            result.push(' ');
+        }
+        let color = if word == "|" {
+            command_position = true;
+            result.push_str(&style(word).fg(Color::White).to_string());
+            continue;
+        } else if command_position {
+            Color::Cyan
+        } else if word.starts_with('-') {
+            Color::Yellow
+        } else {
+            Color::White
+        };
+        command_position = false;
+        result.push_str(&style(word).fg(color).to_string());
+    }
+
+    result
+}
+
+/// `(1.2s)`, or `(1.2s · 45 tok/s)` once the response is streaming
+pub fn timing_label(elapsed: f32, tokens_per_second: Option<f32>) -> String {
+    match tokens_per_second {
+        Some(rate) => format!("({:.1}s · {:.0} tok/s)", elapsed, rate),
+        None => format!("({:.1}s)", elapsed),
+    }
+}
+
+/// JSON schema for `structured_output`: a short answer plus the suggested commands
+fn structured_response_format() -> Value {
+    json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "shell_suggestions",
+            "strict": true,
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "answer": {
+                        "type": "string",
+                        "description": "Short answer to the request, in markdown"
+                    },
+                    "suggestions": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "command": {
+                                    "type": "string",
+                                    "description": "One exact, runnable shell command"
+                                },
+                                "explanation": {
+                                    "type": "string",
+                                    "description": "What the command does, in a few words"
+                                }
+                            },
+                            "required": ["command", "explanation"],
+                            "additionalProperties": false
+                        }
+                    }
+                },
+                "required": ["answer", "suggestions"],
+                "additionalProperties": false
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct StructuredAnswer {
+    answer: String,
+    #[serde(default)]
+    suggestions: Vec<StructuredSuggestion>,
+}
+
+#[derive(Deserialize)]
+struct StructuredSuggestion {
+    command: String,
+    #[serde(default)]
+    explanation: String,
+}
+
+/// A `structured_output` answer as the text shown to the user (the answer, then a
+/// numbered `explanation: `command`` list) and its commands. `None` when the
+/// response isn't the expected JSON, e.g. from a model that ignored `response_format`
+pub fn parse_structured_answer(response: &str) -> Option<(String, Vec<String>)> {
+    let json = response.trim();
+    // Some models wrap the JSON in a fence even when asked not to
+    let json = json
+        .strip_prefix("```json")
+        .or_else(|| json.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(json);
+    let parsed: StructuredAnswer = serde_json::from_str(json.trim()).ok()?;
+
+    let suggestions: Vec<StructuredSuggestion> = parsed
+        .suggestions
+        .into_iter()
+        .filter(|suggestion| !suggestion.command.trim().is_empty())
+        .collect();
+    let mut text = parsed.answer.trim().to_string();
+    for (i, suggestion) in suggestions.iter().enumerate() {
+        if !text.is_empty() {
+            text.push_str(if i == 0 { "\n\n" } else { "\n" });
+        }
+        let explanation = suggestion.explanation.trim().trim_end_matches('.');
+        let explanation = if explanation.is_empty() { "Run" } else { explanation };
+        text.push_str(&format!("{}. {}: `{}`", i + 1, explanation, suggestion.command.trim()));
+    }
+
+    let commands = suggestions
+        .into_iter()
+        .map(|suggestion| suggestion.command.trim().to_string())
+        .collect();
+    Some((text, commands))
+}
+
+/// A request refused because the model can't do `response_format`
+fn rejects_response_format(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ApiError>().is_some_and(|e| {
+        let message = e.message.to_lowercase();
+        matches!(e.status, 400 | 404 | 422)
+            && (message.contains("response_format") || message.contains("structured"))
+    })
+}
+
+/// Whether the model a provider reports answering is `requested`: the same id, or the
+/// same id with a release date (`openai/gpt-4.1-2025-04-14`). Longer ids such as
+/// `openai/gpt-4.1-mini` are different models
+pub fn is_same_model(served: &str, requested: &str) -> bool {
+    let Some(suffix) = served.strip_prefix(requested) else {
+        return false;
+    };
+    let is_date = |date: &str| {
+        let bytes = date.as_bytes();
+        bytes.len() == 10
+            && bytes.iter().enumerate().all(|(i, byte)| match i {
+                4 | 7 => *byte == b'-',
+                _ => byte.is_ascii_digit(),
+            })
+    };
+    suffix.is_empty() || suffix.strip_prefix('-').is_some_and(is_date)
+}
+
+/// `context_length` of each model in an OpenRouter `/models` response
+pub fn parse_context_limits(json: &Value) -> BTreeMap<String, usize> {
+    json["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            let id = model["id"].as_str()?;
+            let limit = model["context_length"].as_u64()?;
+            Some((id.to_string(), limit as usize))
+        })
+        .collect()
+}
+
+/// Proxy from the environment; `HTTPS_PROXY` wins over `ALL_PROXY`, in either case
+pub fn proxy_from_env() -> Option<String> {
+    proxy_env_var().map(|(_, value)| value)
+}
+
+/// The variable `proxy_from_env` takes the proxy from, and its value
+pub fn proxy_env_var() -> Option<(&'static str, String)> {
+    ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok().map(|value| (name, value)))
+        .find(|(_, value)| !value.is_empty())
 }
 
 impl OpenRouterClient {
@@ -16,68 +479,259 @@ impl OpenRouterClient {
             client: Client::new(),
             api_key,
             model,
+            api_url: OPENROUTER_API_URL.to_string(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            quiet: false,
+            markdown: false,
+            request_slots: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+            finish_reason: std::sync::Mutex::new(None),
+            stop: Vec::new(),
+            fallback_models: Vec::new(),
+            served_model: std::sync::Mutex::new(None),
+            token_rate: std::sync::Mutex::new(TokenRate::new(TOKEN_RATE_WINDOW)),
+            early_commands: std::sync::Mutex::new(CommandScanner::default()),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            cache: None,
+            skip_cache: AtomicBool::new(false),
+            cache_hit: AtomicBool::new(false),
+            structured: AtomicBool::new(false),
+            structured_commands: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Skip the spinner and timing line and print the raw response only
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Render responses as markdown instead of the lightweight backtick highlighter
+    pub fn with_markdown(mut self, markdown: bool) -> Self {
+        self.markdown = markdown;
+        self
+    }
+
+    /// How many chat completions may run at once (at least one)
+    pub fn with_max_concurrent_requests(mut self, limit: usize) -> Self {
+        self.request_slots = Arc::new(Semaphore::new(limit.max(1)));
+        self
+    }
+
+    /// Overall limit for a request, including the whole streamed response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Route requests through `proxy`, or `HTTPS_PROXY` / `ALL_PROXY` when it is unset.
+    /// `accept_invalid_certs` turns off TLS verification for self-signed corporate proxies
+    pub fn with_proxy(mut self, proxy: Option<&str>, accept_invalid_certs: bool) -> Self {
+        let mut builder = Client::builder();
+        if let Some(url) = proxy.map(str::to_string).or_else(proxy_from_env) {
+            match reqwest::Proxy::all(&url) {
+                Ok(proxy) => builder = builder.proxy(proxy.no_proxy(reqwest::NoProxy::from_env())),
+                Err(e) => log::warn!("Ignoring invalid proxy URL: {}", e),
+            }
+        }
+        if accept_invalid_certs {
+            log::warn!("TLS certificate verification is disabled (danger_accept_invalid_certs)");
+            builder = builder.danger_accept_invalid_certs(true);
         }
+
+        match builder.build() {
+            Ok(client) => self.client = client,
+            Err(e) => log::warn!("Could not configure the HTTP client, using defaults: {}", e),
+        }
+        self
+    }
+
+    /// Replace the `shy/<version>` User-Agent, for gateways that filter on it. Set per
+    /// request, so it survives the client being rebuilt by `with_proxy`
+    pub fn with_user_agent(mut self, user_agent: Option<&str>) -> Self {
+        if let Some(user_agent) = user_agent {
+            self.user_agent = user_agent.to_string();
+        }
+        self
+    }
+
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    /// Answer identical requests from `cache` instead of calling the API
+    pub fn with_cache(mut self, cache: Option<AnswerCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Ask the API for the next request even if the cache has an answer; the fresh
+    /// answer replaces the cached one
+    pub fn skip_cache_once(&self) {
+        self.skip_cache.store(true, Ordering::Relaxed);
+    }
+
+    pub fn was_cached(&self) -> bool {
+        self.cache_hit.load(Ordering::Relaxed)
+    }
+
+    /// Request `{answer, suggestions: [{command, explanation}]}` JSON instead of prose
+    pub fn with_structured_output(self, structured: bool) -> Self {
+        self.structured.store(structured, Ordering::Relaxed);
+        self
+    }
+
+    /// Commands of the last answer when it came back as valid structured JSON; `None`
+    /// means the text has to be searched for commands instead
+    pub fn structured_commands(&self) -> Option<Vec<String>> {
+        self.structured_commands.lock().ok().and_then(|commands| commands.clone())
+    }
+
+    /// Models OpenRouter routes to, in order, when the main one errors or is rate-limited
+    pub fn with_fallback_models(mut self, fallback_models: Vec<String>) -> Self {
+        self.fallback_models = fallback_models;
+        self
+    }
+
+    /// Sent as the payload's `stop` field, and cut at locally too in case the
+    /// provider ignores it
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_api_url(mut self, api_url: impl Into<String>) -> Self {
+        self.api_url = api_url.into();
+        self
     }
 
     pub async fn stream_chat_with_timing(
         &self,
+        system: Option<&str>,
         message: &str,
         start_time: std::time::Instant,
         _user_input: &str,
     ) -> Result<String> {
         use std::io::{self, Write};
-        use std::time::Duration;
 
-        // Show animated thinking (user input already displayed by REPL)
-        print!(" ");
+        let _slot = self.acquire_request_slot().await?;
+
+        if self.quiet {
+            // No cursor movement or colors, so redirected output stays clean
+            let response = self.stream_chat_internal(system, message).await?;
+            println!("{}", response.trim_end());
+            if self.was_cancelled() {
+                eprintln!("(cancelled, partial response shown)");
+            }
+            return Ok(response);
+        }
+
+        // Progress goes to stderr so `shy ... > answer.txt` only captures the answer
+        let response = self
+            .wait_with_spinner(system, message, start_time, &mut io::stderr())
+            .await?;
+
+        // Print response
+        println!();
+        self.print_with_syntax_highlighting(&response);
+        println!(); // Move to next line
+        if self.was_cancelled() {
+            eprintln!("{}", style("(cancelled, partial response shown)").for_stderr().dim());
+        }
+        if self.was_cached() {
+            eprintln!(
+                "{}",
+                style("(cached answer; /nocache asks again)").for_stderr().dim()
+            );
+        }
+
+        // Ensure output is flushed and terminal is ready for interactive elements
         io::stdout().flush().unwrap();
 
+        Ok(response)
+    }
+
+    /// Run the request while animating a spinner and the elapsed time on `progress`,
+    /// ending with the final timing on its own line
+    pub async fn wait_with_spinner<W: std::io::Write>(
+        &self,
+        system: Option<&str>,
+        message: &str,
+        start_time: std::time::Instant,
+        progress: &mut W,
+    ) -> Result<String> {
+        // Show animated thinking (user input already displayed by REPL)
+        write!(progress, " ").ok();
+        progress.flush().ok();
+
         // Animate spinner
         let spinner_chars = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
         let mut spinner_index = 0;
 
         // Start the API call in a separate task
-        let api_future = self.stream_chat_internal(message);
+        let api_future = self.stream_chat_internal(system, message);
         let mut api_future = Box::pin(api_future);
 
+        // Suggestions printed above the spinner as they complete, erased again before
+        // the full answer is shown
+        let mut shown_commands = 0;
+
         loop {
-            // Update spinner with continuous time display
+            let width = (console::Term::stderr().size().1 as usize).saturating_sub(1);
+            let early_commands = self.early_commands();
+            for command in early_commands.iter().skip(shown_commands) {
+                shown_commands += 1;
+                // Kept to one terminal row so the erase below removes exactly these lines
+                let command: String = command.chars().take(width.saturating_sub(8)).collect();
+                write!(
+                    progress,
+                    "\r\x1b[2K  {} {}\n",
+                    style(format!("{}.", shown_commands)).for_stderr().dim(),
+                    highlight_command(&command)
+                )
+                .ok();
+            }
+
+            // Update spinner with continuous time display, and speed once tokens arrive
             let elapsed = start_time.elapsed().as_secs_f32();
-            print!(
+            let rate = self.token_rate().and_then(|mut rate| rate.per_second(Instant::now()));
+            write!(
+                progress,
                 " {} {}",
-                style(spinner_chars[spinner_index]).fg(Color::Cyan),
-                style(format!("({:.1}s)", elapsed)).fg(Color::Yellow)
-            );
-            io::stdout().flush().unwrap();
+                style(spinner_chars[spinner_index]).for_stderr().fg(Color::Cyan),
+                style(timing_label(elapsed, rate)).for_stderr().fg(Color::Yellow)
+            )
+            .ok();
+            progress.flush().ok();
 
             // Check if API call is done
             match tokio::time::timeout(Duration::from_millis(80), &mut api_future).await {
                 Ok(result) => {
-                    // API call completed
-                    let response = result?;
-
-                    // Clear the entire spinner line completely and show clean final timing
+                    // Clear the entire spinner line completely and show clean final timing,
+                    // also when the request failed
                     let final_time = start_time.elapsed().as_secs_f32();
-                    print!(
-                        "\r{}\r {}\n",
-                        " ".repeat(50), // Clear the entire line first
-                        style(format!("({:.1}s)", final_time)).fg(Color::Yellow)
-                    );
-
-                    // Print response
-                    println!();
-                    self.print_with_syntax_highlighting(&response);
-                    println!(); // Move to next line
-                    
-                    // Ensure output is flushed and terminal is ready for interactive elements
-                    use std::io::{self, Write};
-                    io::stdout().flush().unwrap();
-
-                    return Ok(response);
+                    let rate = self.token_rate().and_then(|rate| rate.average(Instant::now()));
+                    write!(progress, "\r\x1b[2K{}", "\x1b[1A\x1b[2K".repeat(shown_commands)).ok();
+                    // Clear the spinner without wrapping, even if the window just shrank
+                    let served = self
+                        .fallback_model_used()
+                        .map(|model| format!(" answered by {}", model))
+                        .unwrap_or_default();
+                    write!(
+                        progress,
+                        "\r{}\r {}{}\n",
+                        " ".repeat(width.min(50)), // Clear the entire line first
+                        style(timing_label(final_time, rate)).for_stderr().fg(Color::Yellow),
+                        style(served).for_stderr().dim()
+                    )
+                    .ok();
+                    progress.flush().ok();
+
+                    return result;
                 }
                 Err(_) => {
                     // Timeout, continue spinning - clear the line for next update
-                    print!("\r");
+                    write!(progress, "\r").ok();
                     spinner_index = (spinner_index + 1) % spinner_chars.len();
                 }
             }
@@ -86,191 +740,564 @@ impl OpenRouterClient {
 
     #[allow(dead_code)]
     pub async fn stream_chat(&self, message: &str) -> Result<String> {
-        self.stream_chat_internal(message).await
+        self.complete(None, message).await
     }
 
-    async fn stream_chat_internal(&self, message: &str) -> Result<String> {
-        let payload = json!({
-            "model": self.model,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": message
+    /// Collect a whole response without printing anything
+    pub async fn complete(&self, system: Option<&str>, message: &str) -> Result<String> {
+        let _slot = self.acquire_request_slot().await?;
+        self.stream_chat_internal(system, message).await
+    }
+
+    /// Wait for a free request slot, saying so when an earlier request holds it.
+    /// Waiting does not count towards the request timeout.
+    async fn acquire_request_slot(&self) -> Result<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.request_slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        if !self.quiet {
+            eprintln!(
+                " {}",
+                style("waiting for the previous request to finish…").for_stderr().dim()
+            );
+        }
+        Ok(self.request_slots.clone().acquire_owned().await?)
+    }
+
+    /// Collect the response (see `stream_chat_raw`). A structured answer is turned back
+    /// into the usual numbered list, with its commands kept for `structured_commands`
+    async fn stream_chat_internal(&self, system: Option<&str>, message: &str) -> Result<String> {
+        if let Ok(mut commands) = self.structured_commands.lock() {
+            *commands = None;
+        }
+
+        let response = match self.stream_chat_raw(system, message).await {
+            Err(e) if self.structured.load(Ordering::Relaxed) && rejects_response_format(&e) => {
+                log::warn!("{} does not support structured output; asking for prose", self.model);
+                self.structured.store(false, Ordering::Relaxed);
+                self.stream_chat_raw(system, message).await?
+            }
+            result => result?,
+        };
+        if !self.structured.load(Ordering::Relaxed) {
+            return Ok(response);
+        }
+
+        match parse_structured_answer(&response) {
+            Some((text, commands)) => {
+                if let Ok(mut structured_commands) = self.structured_commands.lock() {
+                    *structured_commands = Some(commands);
+                }
+                Ok(text)
+            }
+            None => {
+                log::debug!("structured answer is not valid JSON; extracting commands from the text");
+                Ok(response)
+            }
+        }
+    }
+
+    /// Collect the response, giving up after the timeout. Ctrl-C stops the stream and
+    /// returns whatever arrived so far, with `was_cancelled` reporting it afterwards
+    async fn stream_chat_raw(&self, system: Option<&str>, message: &str) -> Result<String> {
+        let skip_cache = self.skip_cache.swap(false, Ordering::Relaxed);
+        self.cache_hit.store(false, Ordering::Relaxed);
+        let cached = self
+            .cache
+            .as_ref()
+            .map(|cache| (cache, AnswerCache::key(&self.build_payload(system, message))));
+        if let Some((cache, key)) = cached.as_ref().filter(|_| !skip_cache) {
+            if let Some(response) = cache.get(key) {
+                log::debug!("answer cache hit {}", key);
+                self.cache_hit.store(true, Ordering::Relaxed);
+                if let Ok(mut finish_reason) = self.finish_reason.lock() {
+                    *finish_reason = Some("stop".to_string());
+                }
+                if let Ok(mut token_rate) = self.token_rate.lock() {
+                    *token_rate = TokenRate::new(TOKEN_RATE_WINDOW);
+                }
+                if let Ok(mut early_commands) = self.early_commands.lock() {
+                    *early_commands = CommandScanner::default();
+                }
+                // Whoever answered the last request didn't answer this one
+                if let Ok(mut served_model) = self.served_model.lock() {
+                    *served_model = None;
                 }
-            ],
+                return Ok(response);
+            }
+        }
+
+        let mut partial = String::new();
+        let outcome = tokio::select! {
+            result = tokio::time::timeout(
+                self.timeout,
+                self.send_and_collect(system, message, &mut partial),
+            ) => Some(result),
+            _ = tokio::signal::ctrl_c() => None,
+        };
+
+        match outcome {
+            Some(Ok(result)) => {
+                result?;
+                // Only complete answers are worth reusing
+                let complete = self.last_finish_reason().as_deref() != Some("length");
+                if let Some((cache, key)) = cached.filter(|_| complete && !partial.trim().is_empty()) {
+                    if let Err(e) = cache.store(&key, &self.model, &partial) {
+                        log::warn!("Could not cache the answer: {}", e);
+                    }
+                }
+                Ok(partial)
+            }
+            Some(Err(_)) => anyhow::bail!(
+                "Request timed out after {}s without a complete response from {}",
+                self.timeout.as_secs_f32(),
+                self.model
+            ),
+            None => {
+                log::debug!("cancelled after {} bytes", partial.len());
+                if let Ok(mut finish_reason) = self.finish_reason.lock() {
+                    *finish_reason = Some(CANCELLED.to_string());
+                }
+                Ok(partial)
+            }
+        }
+    }
+
+    /// Verify the API key against OpenRouter's key info endpoint
+    pub async fn check_api_key(&self) -> Result<()> {
+        let response = self
+            .client
+            .get(OPENROUTER_KEY_URL)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header(reqwest::header::USER_AGENT, &self.user_agent)
+            .timeout(self.timeout)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(ApiError::from_response(status, &error_text).into());
+        }
+
+        Ok(())
+    }
+
+    /// GET a text resource such as a shared prompt template, with the same proxy,
+    /// User-Agent and timeout as API requests
+    pub async fn fetch_text(&self, url: &str) -> Result<String> {
+        let response = self
+            .client
+            .get(url)
+            .header(reqwest::header::USER_AGENT, &self.user_agent)
+            .timeout(self.timeout)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("{} returned HTTP {}", url, response.status().as_u16());
+        }
+        Ok(response.text().await?)
+    }
+
+    /// IDs of all models currently offered by OpenRouter
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        let json = self.fetch_model_list(self.timeout).await?;
+        let models = json["data"]
+            .as_array()
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|model| model["id"].as_str().map(|id| id.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(models)
+    }
+
+    /// Context window in tokens of every model that reports one
+    pub async fn context_limits(&self) -> Result<BTreeMap<String, usize>> {
+        let timeout = self.timeout.min(CONTEXT_LIMITS_TIMEOUT);
+        Ok(parse_context_limits(&self.fetch_model_list(timeout).await?))
+    }
+
+    /// Whether requests go to OpenRouter, whose model list describes the models
+    pub fn uses_openrouter(&self) -> bool {
+        self.api_url == OPENROUTER_API_URL
+    }
+
+    async fn fetch_model_list(&self, timeout: Duration) -> Result<Value> {
+        let response = self
+            .client
+            .get(OPENROUTER_MODELS_URL)
+            .header(reqwest::header::USER_AGENT, &self.user_agent)
+            .timeout(timeout)
+            .send()
+            .await?;
+
+        log::debug!("response status: {}", response.status());
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            anyhow::bail!("Model list request failed: {}", error_text);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// The exact JSON body sent to the chat completions endpoint
+    pub fn build_payload(&self, system: Option<&str>, message: &str) -> Value {
+        let mut messages = Vec::new();
+        if let Some(system) = system {
+            messages.push(json!({
+                "role": "system",
+                "content": system
+            }));
+        }
+        messages.push(json!({
+            "role": "user",
+            "content": message
+        }));
+
+        let mut payload = json!({
+            "model": self.model,
+            "messages": messages,
             "stream": true
         });
+        if !self.stop.is_empty() {
+            payload["stop"] = json!(self.stop);
+        }
+        if !self.fallback_models.is_empty() {
+            let mut models = vec![self.model.clone()];
+            for model in &self.fallback_models {
+                if !models.contains(model) {
+                    models.push(model.clone());
+                }
+            }
+            payload["models"] = json!(models);
+        }
+        if self.structured.load(Ordering::Relaxed) {
+            payload["response_format"] = structured_response_format();
+        }
+        payload
+    }
+
+    /// Stream the response into `full_response`, so a cancelled request keeps its text
+    async fn send_and_collect(
+        &self,
+        system: Option<&str>,
+        message: &str,
+        full_response: &mut String,
+    ) -> Result<()> {
+        let payload = self.build_payload(system, message);
+        if let Ok(mut finish_reason) = self.finish_reason.lock() {
+            *finish_reason = None;
+        }
+        if let Ok(mut token_rate) = self.token_rate.lock() {
+            *token_rate = TokenRate::new(TOKEN_RATE_WINDOW);
+        }
+        if let Ok(mut early_commands) = self.early_commands.lock() {
+            *early_commands = CommandScanner::default();
+        }
+        if let Ok(mut served_model) = self.served_model.lock() {
+            *served_model = None;
+        }
+        log::debug!("POST {} payload: {}", self.api_url, payload);
 
         let response = self
             .client
-            .post("https://openrouter.ai/api/v1/chat/completions")
+            .post(&self.api_url)
             .header("Authorization", format!("Bearer {}", self.api_key))
+            .header(reqwest::header::USER_AGENT, &self.user_agent)
             .header("Content-Type", "application/json")
             .json(&payload)
             .send()
             .await?;
 
+        log::debug!("response status: {}", response.status());
         if !response.status().is_success() {
+            let status = response.status().as_u16();
             let error_text = response.text().await?;
-            anyhow::bail!("API request failed: {}", error_text);
+            return Err(ApiError::from_response(status, &error_text).into());
         }
 
         let mut stream = response.bytes_stream();
-        let mut first_token = true;
-        let mut full_response = String::new();
+        let mut decoder = SseDecoder::default();
 
         while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            let chunk_str = String::from_utf8_lossy(&chunk);
+            for data in decoder.push(&chunk?) {
+                if !self.handle_event(&data, full_response) {
+                    return Ok(());
+                }
+            }
+        }
+        if let Some(data) = decoder.finish() {
+            self.handle_event(&data, full_response);
+        }
 
-            for line in chunk_str.lines() {
-                if line.starts_with("data: ") {
-                    let data = line.strip_prefix("data: ").unwrap();
+        Ok(())
+    }
 
-                    if data == "[DONE]" {
-                        break;
-                    }
+    /// Apply one SSE payload; `false` once the stream says it is done
+    fn handle_event(&self, data: &str, full_response: &mut String) -> bool {
+        log::debug!("sse raw: {}", data);
+        if data == "[DONE]" {
+            return false;
+        }
 
-                    if let Some(content) = self.extract_content_from_json(data) {
-                        if first_token {
-                            first_token = false;
-                        }
-                        full_response.push_str(&content);
+        if let Some(model) = Self::extract_model(data) {
+            if let Ok(mut served_model) = self.served_model.lock() {
+                served_model.get_or_insert(model);
+            }
+        }
+
+        if let Some(reason) = Self::extract_finish_reason(data) {
+            log::debug!("finish reason: {}", reason);
+            if let Ok(mut finish_reason) = self.finish_reason.lock() {
+                *finish_reason = Some(reason);
+            }
+        }
+
+        match self.extract_content_from_json(data) {
+            Some(content) => {
+                log::debug!("sse content: {:?}", content);
+                if let Ok(mut token_rate) = self.token_rate.lock() {
+                    token_rate.record(Instant::now(), TokenRate::estimate_tokens(&content));
+                }
+                full_response.push_str(&content);
+                if content.contains('\n') {
+                    if let Ok(mut early_commands) = self.early_commands.lock() {
+                        early_commands.scan(full_response);
+                    }
+                }
+                if let Some(end) = self.stop_position(full_response) {
+                    log::debug!("stop sequence reached at byte {}", end);
+                    full_response.truncate(end);
+                    if let Ok(mut finish_reason) = self.finish_reason.lock() {
+                        *finish_reason = Some("stop".to_string());
                     }
+                    return false;
                 }
             }
+            None if serde_json::from_str::<Value>(data).is_err() => {
+                log::warn!("skipping malformed SSE event: {}", data);
+            }
+            None => {}
         }
+        true
+    }
 
-        Ok(full_response)
+    /// Where the earliest stop sequence starts in `text`, if one has arrived
+    fn stop_position(&self, text: &str) -> Option<usize> {
+        self.stop
+            .iter()
+            .filter(|stop| !stop.is_empty())
+            .filter_map(|stop| text.find(stop.as_str()))
+            .min()
     }
 
     fn extract_content_from_json(&self, data: &str) -> Option<String> {
         let json = serde_json::from_str::<Value>(data).ok()?;
         let choices = json["choices"].as_array()?;
         let choice = choices.first()?;
-        let delta = choice["delta"].as_object()?;
-        delta["content"].as_str().map(|s| s.to_string())
+        // The final chunk often has an empty delta and only a finish_reason
+        choice["delta"]["content"].as_str().map(|s| s.to_string())
+    }
+
+    fn extract_finish_reason(data: &str) -> Option<String> {
+        let json = serde_json::from_str::<Value>(data).ok()?;
+        json["choices"][0]["finish_reason"]
+            .as_str()
+            .map(|s| s.to_string())
     }
 
-    fn print_with_syntax_highlighting(&self, text: &str) {
+    fn extract_model(data: &str) -> Option<String> {
+        let json = serde_json::from_str::<Value>(data).ok()?;
+        json["model"].as_str().filter(|model| !model.is_empty()).map(|s| s.to_string())
+    }
+
+    /// The fallback model that answered the last request, when it wasn't `model`
+    pub fn fallback_model_used(&self) -> Option<String> {
+        if self.fallback_models.is_empty() {
+            return None;
+        }
+        let served = self.served_model.lock().ok()?.clone()?;
+        (!is_same_model(&served, &self.model)).then_some(served)
+    }
+
+    /// The model that answered the last request as the provider reported it, or the
+    /// requested model when it didn't say
+    pub fn answering_model(&self) -> String {
+        self.served_model
+            .lock()
+            .ok()
+            .and_then(|served| served.clone())
+            .unwrap_or_else(|| self.model.clone())
+    }
+
+    /// Why the last response ended (`stop`, `length`, `content_filter`, ...), if the
+    /// provider said
+    pub fn last_finish_reason(&self) -> Option<String> {
+        self.finish_reason.lock().ok()?.clone()
+    }
+
+    fn token_rate(&self) -> Option<std::sync::MutexGuard<'_, TokenRate>> {
+        self.token_rate.lock().ok()
+    }
+
+    /// Suggestions completed so far in the response being streamed
+    pub fn early_commands(&self) -> Vec<String> {
+        self.early_commands
+            .lock()
+            .map(|scanner| scanner.commands().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Whether the last response was cut short with Ctrl-C
+    pub fn was_cancelled(&self) -> bool {
+        self.last_finish_reason().as_deref() == Some(CANCELLED)
+    }
+
+    pub fn print_with_syntax_highlighting(&self, text: &str) {
+        // Only wrap for interactive terminals; piped output keeps the raw lines
+        let term = console::Term::stdout();
+        let width = term.is_term().then(|| term.size().1 as usize);
+
+        if self.markdown {
+            print!("{}", render_markdown(text, width));
+        } else {
+            print!("{}", self.render_with_syntax_highlighting(text, width));
+        }
+    }
+
+    /// Highlight backticked spans and wrap at word boundaries to `width` columns.
+    /// Backticked spans are never split across lines.
+    pub fn render_with_syntax_highlighting(&self, text: &str, width: Option<usize>) -> String {
         let mut result = String::new();
         let chars = text.chars().peekable();
         let mut in_backticks = false;
         let mut current_word = String::new();
+        // Styled text of the word being built, e.g. `ls`, with trailing punctuation
+        let mut current_group = String::new();
+        let mut pending_whitespace = String::new();
+        let mut column = 0;
 
         for ch in chars {
             if ch == '`' {
                 if in_backticks {
                     // End of backticked content - apply syntax highlighting
-                    result.push_str(&self.format_code_element(&current_word));
+                    current_group.push_str(&self.format_code_element(&current_word));
                     current_word.clear();
                     in_backticks = false;
                 } else {
                     // Start of backticked content
-                    if !current_word.is_empty() {
-                        result.push_str(&current_word);
-                        current_word.clear();
-                    }
+                    current_group.push_str(&current_word);
+                    current_word.clear();
                     in_backticks = true;
                 }
             } else if in_backticks {
                 current_word.push(ch);
             } else if ch == ' ' || ch == '\n' || ch == '\t' {
-                if !current_word.is_empty() {
-                    result.push_str(&current_word);
-                    current_word.clear();
+                current_group.push_str(&current_word);
+                current_word.clear();
+                Self::place_word(
+                    &mut result,
+                    &mut column,
+                    &mut pending_whitespace,
+                    &current_group,
+                    width,
+                );
+                current_group.clear();
+
+                if ch == '\n' {
+                    result.push('\n');
+                    pending_whitespace.clear();
+                    column = 0;
+                } else {
+                    pending_whitespace.push(ch);
                 }
-                result.push(ch);
             } else {
                 current_word.push(ch);
             }
         }
 
         // Handle any remaining content
-        if !current_word.is_empty() {
-            if in_backticks {
-                result.push_str(&self.format_code_element(&current_word));
-            } else {
-                result.push_str(&current_word);
-            }
+        if in_backticks {
+            current_group.push_str(&self.format_code_element(&current_word));
+        } else {
+            current_group.push_str(&current_word);
         }
+        Self::place_word(
+            &mut result,
+            &mut column,
+            &mut pending_whitespace,
+            &current_group,
+            width,
+        );
+        result.push_str(&pending_whitespace);
 
-        print!("{}", result);
+        result
     }
 
-    fn format_code_element(&self, text: &str) -> String {
-        let trimmed = text.trim();
-
-        // Handle pipe commands specially
-        if trimmed.contains('|') {
-            return self.format_pipe_command(trimmed);
+    fn place_word(
+        result: &mut String,
+        column: &mut usize,
+        pending_whitespace: &mut String,
+        word: &str,
+        width: Option<usize>,
+    ) {
+        if word.is_empty() {
+            return;
         }
 
-        // Check if it's a multi-part command
-        let parts: Vec<&str> = trimmed.split_whitespace().collect();
-        if parts.len() > 1 {
-            // Multi-part command - format each part without backticks
-            let mut result = String::new();
-
-            // First part (command) in cyan
-            result.push_str(&style(&parts[0]).fg(Color::Cyan).to_string());
+        let word_width = console::measure_text_width(word.split('\n').next().unwrap_or(""));
+        let spacing = pending_whitespace.chars().count();
 
-            for part in &parts[1..] {
-                result.push(' ');
-                if part.starts_with('-') {
-                    // Flags in yellow
-                    result.push_str(&style(part).fg(Color::Yellow).to_string());
-                } else {
-                    // Arguments in white
-                    result.push_str(&style(part).fg(Color::White).to_string());
-                }
-            }
-            result
-        } else {
-            // Single element without backticks
-            if trimmed.starts_with('-') {
-                // Command flags in yellow
-                style(trimmed).fg(Color::Yellow).to_string()
-            } else if self.looks_like_command(trimmed) {
-                // Commands in cyan
-                style(trimmed).fg(Color::Cyan).to_string()
-            } else {
-                // General code in white (consistent with arguments)
-                style(trimmed).fg(Color::White).to_string()
+        // Break before the word if it would overflow, unless the line is empty
+        if let Some(width) = width {
+            if *column > 0 && *column + spacing + word_width > width {
+                result.push('\n');
+                pending_whitespace.clear();
+                *column = 0;
             }
         }
-    }
 
-    fn format_pipe_command(&self, text: &str) -> String {
-        let pipe_parts: Vec<&str> = text.split('|').collect();
-        let mut result = String::new();
+        result.push_str(pending_whitespace);
+        *column += pending_whitespace.chars().count();
+        pending_whitespace.clear();
+        result.push_str(word);
 
-        for (i, pipe_part) in pipe_parts.iter().enumerate() {
-            if i > 0 {
-                result.push_str(&style(" | ").fg(Color::White).to_string());
-            }
+        // Code blocks may span lines; continue counting from the last one
+        match word.rfind('\n') {
+            Some(i) => *column = console::measure_text_width(&word[i + 1..]),
+            None => *column += word_width,
+        }
+    }
 
-            let trimmed_part = pipe_part.trim();
-            let parts: Vec<&str> = trimmed_part.split_whitespace().collect();
-
-            if !parts.is_empty() {
-                // First part (command) in cyan
-                result.push_str(&style(&parts[0]).fg(Color::Cyan).to_string());
-
-                for part in &parts[1..] {
-                    result.push(' ');
-                    if part.starts_with('-') {
-                        // Flags in yellow
-                        result.push_str(&style(part).fg(Color::Yellow).to_string());
-                    } else {
-                        // Arguments in white
-                        result.push_str(&style(part).fg(Color::White).to_string());
-                    }
-                }
-            }
+    fn format_code_element(&self, text: &str) -> String {
+        let trimmed = text.trim();
+
+        // Multi-word commands get command/flag/argument colors
+        if shell_words(trimmed).len() > 1 {
+            return highlight_command(trimmed);
         }
 
-        result
+        // Single element without backticks
+        if trimmed.starts_with('-') {
+            // Command flags in yellow
+            style(trimmed).fg(Color::Yellow).to_string()
+        } else if self.looks_like_command(trimmed) {
+            // Commands in cyan
+            style(trimmed).fg(Color::Cyan).to_string()
+        } else {
+            // General code in white (consistent with arguments)
+            style(trimmed).fg(Color::White).to_string()
+        }
     }
 
     fn looks_like_command(&self, text: &str) -> bool {
@@ -331,3 +1358,15 @@ impl OpenRouterClient {
                 .all(|c| c.is_ascii_lowercase() || c == '-' || c == '_')
     }
 }
+
+/// Render markdown (lists, emphasis, headers, code blocks) for the terminal, with
+/// inline code and code blocks in the same cyan as the default highlighter
+pub fn render_markdown(text: &str, width: Option<usize>) -> String {
+    use termimad::crossterm::style::Color as TermColor;
+
+    let mut skin = termimad::MadSkin::default();
+    skin.inline_code.set_fg(TermColor::Cyan);
+    skin.code_block.set_fg(TermColor::Cyan);
+    skin.bold.set_fg(TermColor::White);
+    skin.text(text, width).to_string()
+}
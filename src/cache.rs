@@ -0,0 +1,91 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Answers kept on disk by a hash of the whole request payload, so asking the same
+/// question with the same context and model again doesn't cost another call
+#[derive(Debug, Clone)]
+pub struct AnswerCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedAnswer {
+    /// Seconds since the Unix epoch
+    created: i64,
+    model: String,
+    response: String,
+}
+
+impl AnswerCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// The cache under the config directory, when `cache_answers` is on
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if !config.cache_answers {
+            return None;
+        }
+        let dir = Config::config_dir().ok()?.join("cache").join("answers");
+        Some(Self::new(dir, Duration::from_secs(config.cache_ttl_secs)))
+    }
+
+    /// Stable across builds (unlike `DefaultHasher`), so entries survive an upgrade
+    pub fn key(payload: &serde_json::Value) -> String {
+        // 64-bit FNV-1a
+        let hash = payload.to_string().bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        format!("{:016x}", hash)
+    }
+
+    /// The stored answer for `key`, unless it is older than the TTL
+    pub fn get(&self, key: &str) -> Option<String> {
+        let contents = fs::read_to_string(self.path(key)).ok()?;
+        let entry: CachedAnswer = serde_json::from_str(&contents).ok()?;
+        self.is_fresh(&entry).then_some(entry.response)
+    }
+
+    /// Store an answer, dropping expired entries on the way
+    pub fn store(&self, key: &str, model: &str, response: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        self.prune();
+        let entry = CachedAnswer {
+            created: chrono::Utc::now().timestamp(),
+            model: model.to_string(),
+            response: response.to_string(),
+        };
+        fs::write(self.path(key), serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    fn is_fresh(&self, entry: &CachedAnswer) -> bool {
+        let age = chrono::Utc::now().timestamp() - entry.created;
+        (0..self.ttl.as_secs() as i64).contains(&age)
+    }
+
+    fn prune(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let fresh = fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<CachedAnswer>(&contents).ok())
+                .is_some_and(|entry| self.is_fresh(&entry));
+            if !fresh {
+                fs::remove_file(&path).ok();
+            }
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
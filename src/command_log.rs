@@ -0,0 +1,84 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+/// The log is moved to `commands.jsonl.1` once it grows past this size
+pub const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// Where an executed command came from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandSource {
+    /// Picked from the AI's suggestions
+    Suggestion,
+    /// Typed by the user: `/run`, a custom command, history or an alias
+    Manual,
+}
+
+/// One line of the command log
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub cwd: String,
+    pub command: String,
+    /// `None` when the command was killed by a signal, could not start, or was
+    /// started in the background
+    pub exit_code: Option<i32>,
+    pub source: CommandSource,
+}
+
+impl LogEntry {
+    pub fn new(command: &str, cwd: &Path, exit_code: Option<i32>, source: CommandSource) -> Self {
+        Self {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            cwd: cwd.display().to_string(),
+            command: command.to_string(),
+            exit_code,
+            source,
+        }
+    }
+}
+
+pub fn log_path() -> Result<PathBuf> {
+    Ok(Config::config_dir()?.join("commands.jsonl"))
+}
+
+/// Append `entry` as one JSON line, rotating the file first if it is over `max_bytes`
+pub fn append(path: &Path, entry: &LogEntry, max_bytes: u64) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::metadata(path).is_ok_and(|meta| meta.len() > max_bytes) {
+        fs::rename(path, rotated_path(path))?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// The last `limit` entries, oldest first. Lines that don't parse are skipped
+pub fn recent(path: &Path, limit: usize) -> Result<Vec<LogEntry>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let entries: Vec<LogEntry> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let skip = entries.len().saturating_sub(limit);
+    Ok(entries.into_iter().skip(skip).collect())
+}
+
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
@@ -1,55 +1,1057 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Raised when the config file exists but cannot be parsed
+#[derive(Debug)]
+pub struct ConfigParseError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(
+                f,
+                "Invalid config file {} (line {}, column {}): {}",
+                self.path.display(),
+                line,
+                column,
+                self.message
+            ),
+            _ => write!(
+                f,
+                "Invalid config file {}: {}",
+                self.path.display(),
+                self.message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigParseError {}
+
+/// Raised when the config file parses but a setting has a value it doesn't allow.
+/// Unlike `ConfigParseError`, fixing the one setting is enough
+#[derive(Debug)]
+pub struct ConfigValidationError {
+    pub path: PathBuf,
+    /// The setting at fault, e.g. `max_suggestions` or `ui.menu_prompt`
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid setting `{}` in {}: {}",
+            self.key,
+            self.path.display(),
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for ConfigValidationError {}
+
+impl ConfigValidationError {
+    fn new(path: &Path, (key, message): (&str, String)) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            key: key.to_string(),
+            message,
+        }
+    }
+}
+
+impl ConfigParseError {
+    fn from_toml(path: &Path, contents: &str, error: &toml::de::Error) -> Self {
+        // Translate the byte span into a 1-based line/column for the user
+        let position = error.span().map(|span| {
+            let before = &contents[..span.start.min(contents.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            (line, column)
+        });
+
+        ConfigParseError {
+            path: path.to_path_buf(),
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            message: error.message().to_string(),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    #[serde(default)]
     pub api_key: String,
+    /// Command whose stdout is used as the API key instead of `api_key`,
+    /// e.g. `pass show openrouter`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_command: Option<String>,
     pub default_model: String,
+    /// Models OpenRouter falls back to, in order, when `default_model` errors or is
+    /// rate-limited
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_models: Vec<String>,
+    /// Whether recent shell history is included in the context sent to the AI
+    /// (`include_history` in older config files)
+    #[serde(default = "default_true", alias = "include_history")]
+    pub send_history: bool,
+    /// Whether the directory listing, project type and contents of files mentioned in
+    /// a message are included in the context sent to the AI
+    #[serde(default = "default_true")]
+    pub send_files: bool,
+    /// Whether secrets are scrubbed from history and file context before sending
+    #[serde(default = "default_true")]
+    pub redact_secrets: bool,
+    /// Extra regex patterns to redact, on top of the built-in secret patterns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+    /// Overall timeout for an AI request, in seconds
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// Automatically re-ask for a runnable command when an answer has none
+    #[serde(default)]
+    pub auto_retry: bool,
+    /// How many numbered command suggestions to ask for and offer in the menu
+    #[serde(default = "default_max_suggestions")]
+    pub max_suggestions: usize,
+    /// Sequences that end a response, sent as the API's `stop` field (at most 4)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Ask for suggestions as JSON (`response_format`) instead of parsing them out of
+    /// prose; falls back to prose for models that don't support it
+    #[serde(default)]
+    pub structured_output: bool,
+    /// When to pop up the command menu after an answer
+    #[serde(default)]
+    pub suggestion_menu: SuggestionMenu,
+    /// Shell that runs commands (`sh`, `bash`, `zsh`, `fish`, `powershell`);
+    /// the detected shell when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    /// Shell shy assumes it was started from (whose history it reads), instead of
+    /// detecting it; also the default for `shell`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell_type: Option<String>,
+    /// Render responses as full markdown instead of the minimal highlighter
+    #[serde(default)]
+    pub markdown: bool,
+    /// How many AI requests may be in flight at once
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// Offer to save an unsaved conversation on `/exit` or Ctrl-D
+    #[serde(default = "default_true")]
+    pub confirm_unsaved_exit: bool,
+    /// Stop a command run from the REPL after this many seconds; 0 waits forever.
+    /// `/run --timeout <secs>` overrides it for one command
+    #[serde(default = "default_command_timeout_secs")]
+    pub command_timeout_secs: u64,
+    /// Show command output taller than the terminal through `$PAGER` (or `less -R`)
+    #[serde(default)]
+    pub use_pager: bool,
+    /// Shared system prompt for a team, used when there is no local `prompt.md`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_url: Option<String>,
+    /// Instructions that replace the built-in system prompt, `prompt.md` and `prompt_url`;
+    /// mostly useful in a project's `.shy.toml`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// How long the downloaded `prompt_url` template is reused before fetching it again
+    #[serde(default = "default_prompt_url_ttl_secs")]
+    pub prompt_url_ttl_secs: u64,
+    /// Reuse the answer to an identical request (same question, context and model)
+    /// instead of calling the API again
+    #[serde(default)]
+    pub cache_answers: bool,
+    /// How long a cached answer is reused
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// HTTP(S) proxy for API requests, e.g. `http://proxy.corp:3128`;
+    /// `HTTPS_PROXY` / `ALL_PROXY` are used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Skip TLS certificate verification. Only for self-signed internal proxies
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// User-Agent sent to the API instead of `shy/<version>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Append every executed command to `commands.jsonl` in the config directory
+    #[serde(default = "default_true")]
+    pub log_commands: bool,
+    /// Tools the AI should prefer, e.g. `["rg", "fd"]`, named before detected ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preferred_tools: Vec<String>,
+    /// Tell the AI which modern CLI tools (`rg`, `fd`, `bat`, ...) are on PATH
+    #[serde(default = "default_true")]
+    pub detect_tools: bool,
+    /// History file chosen with "Change history source" in `/history`; picked from the
+    /// running shell when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_file: Option<PathBuf>,
+    /// Let `shy exec` run suggested commands on `safe_commands` without asking first
+    #[serde(default)]
+    pub auto_run_safe: bool,
+    /// Read-only commands `auto_run_safe` may run unconfirmed; an entry such as
+    /// `git status` matches that command with any further arguments
+    #[serde(default = "default_safe_commands")]
+    pub safe_commands: Vec<String>,
+    /// Offer the built-in next steps after a command (`git diff` after `git status`, ...)
+    #[serde(default = "default_true")]
+    pub builtin_follow_ups: bool,
+    /// Look of the REPL, the `[ui]` section
+    #[serde(default)]
+    pub ui: UiConfig,
+    /// Shortcuts created with `/alias`, run as `/<name>`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    /// Extra next steps offered after a command, the `[[follow_up_rules]]` entries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub follow_up_rules: Vec<FollowUpRule>,
+    /// Named sets of overrides, e.g. `[profiles.work]` with its own key and model
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, toml::Table>,
+    /// Profile whose overrides are applied, if any
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+    /// The key from the config file while `--api-key` replaces it, so saving never
+    /// writes the flag's key to disk
+    #[serde(skip)]
+    pub stored_api_key: Option<String>,
+    /// The project file layered on top by `with_project_config`, if any
+    #[serde(skip)]
+    pub project: Option<ProjectConfig>,
+}
+
+/// Identifies one project prompt by its file and exact text, so editing either asks again
+pub fn project_prompt_key(path: &Path, prompt: &str) -> String {
+    crate::cache::AnswerCache::key(&serde_json::json!([path, prompt]))
+}
+
+/// Whether `key` was accepted before and recorded in the `list` file
+pub fn is_prompt_trusted(list: &Path, key: &str) -> bool {
+    fs::read_to_string(list).is_ok_and(|contents| {
+        contents
+            .lines()
+            .any(|line| line.split_whitespace().next() == Some(key))
+    })
+}
+
+/// Remember that the prompt `key` from `path` may be used from now on
+pub fn trust_prompt(list: &Path, key: &str, path: &Path) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = list.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(list)?;
+    writeln!(file, "{} {}", key, path.display())?;
+    Ok(())
+}
+
+/// Files looked for in the working directory and its parents, nearest first
+pub const PROJECT_CONFIG_NAMES: [&str; 2] = [".shy.toml", ".shyrc"];
+
+/// Settings a project file may change. Keys that run commands, hold the API key or
+/// redirect traffic stay with the user, since the file comes with whatever repo is cloned
+pub const PROJECT_KEYS: [&str; 8] = [
+    "default_model",
+    "fallback_models",
+    "system_prompt",
+    "max_suggestions",
+    "preferred_tools",
+    "stop",
+    "structured_output",
+    "markdown",
+];
+
+/// A project's `.shy.toml` as applied to the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectConfig {
+    pub path: PathBuf,
+    /// The allowed settings the file sets
+    pub values: toml::Table,
+    /// What those settings were before the project file replaced them
+    pub shadowed: toml::Table,
+}
+
+/// The `[ui]` section: prompt indicator, startup banner and suggestion picker
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct UiConfig {
+    /// Shown before the cursor, `〉` by default
+    pub prompt_indicator: String,
+    /// Print the name, model and help hint when the REPL starts
+    pub show_banner: bool,
+    /// How a suggestion is picked after an answer
+    pub menu_style: MenuStyle,
+    /// Question above the arrow-key menu
+    pub menu_prompt: String,
+    /// Entry the arrow-key menu starts on
+    pub menu_default: MenuDefault,
+    /// Let `menu_default = "first"` start on a command, so Enter alone runs it
+    pub allow_run_default: bool,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            prompt_indicator: "〉".to_string(),
+            show_banner: true,
+            menu_style: MenuStyle::default(),
+            menu_prompt: "What would you like to do?".to_string(),
+            menu_default: MenuDefault::default(),
+            allow_run_default: false,
+        }
+    }
+}
+
+/// How the suggestion picker looks
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuStyle {
+    /// An arrow-key menu
+    #[default]
+    Select,
+    /// One line; press a suggestion's number to run it, `c` for a custom command and
+    /// `0` or Esc to skip
+    Keys,
+}
+
+/// Entry the arrow-key suggestion menu starts on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuDefault {
+    /// "Do nothing", so Enter never runs anything
+    #[default]
+    Nothing,
+    /// The first suggestion; needs `allow_run_default = true`
+    First,
+    /// "Enter custom command"
+    Custom,
+}
+
+/// After a successful command matching `command` (and `output`, when set), offer `suggest`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FollowUpRule {
+    /// Regex matched against the command that ran, e.g. `^docker build`
+    pub command: String,
+    /// Regex the command's output must also match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// The next step to offer, e.g. `docker images`
+    pub suggest: String,
+}
+
+/// Controls the menu shown after an answer that contains commands
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionMenu {
+    /// Only when at least one suggested command can actually be run
+    #[default]
+    Auto,
+    /// Whenever anything that looks like a command was extracted
+    Always,
+    /// Never pop up; use `/run` to pick a suggestion
+    Never,
+}
+
+/// Where administrators put the baseline config shared by all users
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/shy/config.toml";
+
+/// Allowed range for `max_suggestions`
+pub const MAX_SUGGESTIONS_RANGE: std::ops::RangeInclusive<usize> = 1..=10;
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_request_timeout_secs() -> u64 {
+    crate::api::DEFAULT_TIMEOUT_SECS
+}
+
+fn default_max_concurrent_requests() -> usize {
+    crate::api::DEFAULT_MAX_CONCURRENT_REQUESTS
+}
+
+fn default_command_timeout_secs() -> u64 {
+    120
+}
+
+fn default_prompt_url_ttl_secs() -> u64 {
+    60 * 60
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_max_suggestions() -> usize {
+    3
+}
+
+fn default_safe_commands() -> Vec<String> {
+    [
+        "ls", "pwd", "cat", "head", "tail", "wc", "whoami", "date", "df", "du", "which",
+        "git status", "git log", "git diff", "git branch",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            api_key_command: None,
+            default_model: AVAILABLE_MODELS[0].id.to_string(),
+            fallback_models: Vec::new(),
+            send_history: true,
+            send_files: true,
+            redact_secrets: true,
+            redact_patterns: Vec::new(),
+            request_timeout_secs: default_request_timeout_secs(),
+            auto_retry: false,
+            max_suggestions: default_max_suggestions(),
+            stop: Vec::new(),
+            structured_output: false,
+            suggestion_menu: SuggestionMenu::default(),
+            shell: None,
+            shell_type: None,
+            markdown: false,
+            max_concurrent_requests: default_max_concurrent_requests(),
+            confirm_unsaved_exit: true,
+            command_timeout_secs: default_command_timeout_secs(),
+            use_pager: false,
+            prompt_url: None,
+            prompt_url_ttl_secs: default_prompt_url_ttl_secs(),
+            system_prompt: None,
+            cache_answers: false,
+            cache_ttl_secs: default_cache_ttl_secs(),
+            proxy: None,
+            danger_accept_invalid_certs: false,
+            user_agent: None,
+            log_commands: true,
+            preferred_tools: Vec::new(),
+            detect_tools: true,
+            history_file: None,
+            auto_run_safe: false,
+            safe_commands: default_safe_commands(),
+            builtin_follow_ups: true,
+            ui: UiConfig::default(),
+            aliases: BTreeMap::new(),
+            follow_up_rules: Vec::new(),
+            profiles: BTreeMap::new(),
+            active_profile: None,
+            stored_api_key: None,
+            project: None,
+        }
+    }
+}
+
+/// Config directory when the platform has none: `~/.shy`, or `.shy` in the working
+/// directory when there isn't even a home directory
+pub fn fallback_config_dir(home: Option<&Path>, cwd: &Path) -> PathBuf {
+    home.unwrap_or(cwd).join(".shy")
+}
+
+/// The directory named by an `XDG_CONFIG_HOME` value. The XDG spec says relative paths
+/// are invalid and should be ignored
+pub fn xdg_config_home(value: Option<&std::ffi::OsStr>) -> Option<PathBuf> {
+    value.map(PathBuf::from).filter(|path| path.is_absolute())
 }
 
 impl Config {
+    /// `$XDG_CONFIG_HOME/shy` on every platform when that is set to an absolute path,
+    /// otherwise the platform default: `~/.config/shy` on Linux,
+    /// `~/Library/Application Support/shy` on macOS, `%APPDATA%\\shy` on Windows.
+    /// Minimal containers may have neither; see `fallback_config_dir`
     pub fn config_dir() -> Result<PathBuf> {
-        let mut path =
-            dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
-        path.push("shy");
+        let xdg = xdg_config_home(std::env::var_os("XDG_CONFIG_HOME").as_deref());
+        if let Some(path) = xdg.or_else(dirs::config_dir) {
+            return Ok(path.join("shy"));
+        }
+
+        let cwd = std::env::current_dir()?;
+        let path = fallback_config_dir(dirs::home_dir().as_deref(), &cwd);
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            log::warn!(
+                "No standard config directory found; using {}",
+                path.display()
+            )
+        });
         Ok(path)
     }
 
+    /// Whether `config_dir` had to use `fallback_config_dir`
+    pub fn uses_fallback_config_dir() -> bool {
+        xdg_config_home(std::env::var_os("XDG_CONFIG_HOME").as_deref()).is_none()
+            && dirs::config_dir().is_none()
+    }
+
+    /// Baseline settings shared by every user of the machine, overridden by `config_path()`
+    pub fn system_config_path() -> PathBuf {
+        PathBuf::from(SYSTEM_CONFIG_PATH)
+    }
+
     pub fn config_path() -> Result<PathBuf> {
         let mut path = Self::config_dir()?;
         path.push("config.toml");
         Ok(path)
     }
 
+    /// Optional custom system prompt that replaces the built-in instructions
+    pub fn prompt_path() -> Result<PathBuf> {
+        let mut path = Self::config_dir()?;
+        path.push("prompt.md");
+        Ok(path)
+    }
+
+    /// Contents of `prompt.md`, if it exists and is not blank
+    pub fn load_custom_prompt() -> Option<String> {
+        let contents = fs::read_to_string(Self::prompt_path().ok()?).ok()?;
+        let trimmed = contents.trim();
+        (!trimmed.is_empty()).then(|| trimmed.to_string())
+    }
+
     pub fn load() -> Result<Self> {
-        let path = Self::config_path()?;
+        let mut config = Self::load_base()?.with_project_config_from_cwd()?;
+        config.resolve_api_key()?;
+        Ok(config)
+    }
+
+    /// The nearest `.shy.toml` or `.shyrc` in `start` or one of its parents
+    pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .flat_map(|dir| PROJECT_CONFIG_NAMES.iter().map(move |name| dir.join(name)))
+            .find(|path| path.is_file())
+    }
+
+    /// This config with the project file for the working directory layered on top
+    pub fn with_project_config_from_cwd(self) -> Result<Self> {
+        let project = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| Self::find_project_config(&cwd));
+        match project {
+            Some(path) => self.with_project_config(&path),
+            None => Ok(self),
+        }
+    }
+
+    /// This config with the `PROJECT_KEYS` set in `path` replacing its own. Other keys in
+    /// the file are ignored with a warning
+    pub fn with_project_config(mut self, path: &Path) -> Result<Self> {
         let contents = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&contents)?;
+        let mut values: toml::Table = toml::from_str(&contents)
+            .map_err(|e| ConfigParseError::from_toml(path, &contents, &e))?;
+        values.retain(|key, _| {
+            let allowed = PROJECT_KEYS.contains(&key);
+            if !allowed {
+                log::warn!(
+                    "Ignoring `{}` in {}: only {} can be set per project",
+                    key,
+                    path.display(),
+                    PROJECT_KEYS.join(", ")
+                );
+            }
+            allowed
+        });
+
+        let mut merged = toml::Table::try_from(&self)?;
+        let shadowed = values
+            .keys()
+            .filter_map(|key| Some((key.clone(), merged.get(key)?.clone())))
+            .collect();
+        merge_tables(&mut merged, values.clone());
+
+        let mut config = Self::from_table(merged, path)?;
+        config.active_profile = self.active_profile.take();
+        config.stored_api_key = self.stored_api_key.take();
+        config.project = Some(ProjectConfig {
+            path: path.to_path_buf(),
+            values,
+            shadowed,
+        });
+        Ok(config)
+    }
+
+    /// The project file and the `system_prompt` it sets, if it sets one
+    pub fn project_system_prompt(&self) -> Option<(&Path, &str)> {
+        let project = self.project.as_ref()?;
+        let prompt = project.values.get("system_prompt")?.as_str()?;
+        Some((&project.path, prompt))
+    }
+
+    /// Go back to the `system_prompt` the project file replaced, as if it didn't set one
+    pub fn without_project_system_prompt(mut self) -> Self {
+        if let Some(project) = &mut self.project {
+            if project.values.remove("system_prompt").is_some() {
+                self.system_prompt = project
+                    .shadowed
+                    .remove("system_prompt")
+                    .and_then(|value| value.as_str().map(String::from));
+            }
+        }
+        self
+    }
+
+    /// Project prompts the user agreed to use, one `<key> <path>` line each
+    pub fn trusted_prompts_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("trusted_prompts"))
+    }
+
+    /// Undo the project file's settings that are still in effect, so saving never copies
+    /// them into the user's config. Settings changed since, e.g. with `/model`, are kept
+    pub(crate) fn without_project(self) -> Result<Self> {
+        let Some(project) = &self.project else {
+            return Ok(self);
+        };
+        let mut table = toml::Table::try_from(&self)?;
+        for (key, value) in &project.values {
+            if table.get(key) != Some(value) {
+                continue;
+            }
+            match project.shadowed.get(key) {
+                Some(original) => table.insert(key.clone(), original.clone()),
+                None => table.remove(key),
+            };
+        }
+        let mut config: Self = toml::Value::Table(table).try_into()?;
+        config.active_profile = self.active_profile;
+        config.stored_api_key = self.stored_api_key;
+        Ok(config)
+    }
+
+    /// The system and user config files as written, before any profile or
+    /// `api_key_command` is applied
+    pub fn load_base() -> Result<Self> {
+        let user = Self::config_path()?;
+        let system = Self::system_config_path();
+        let layers: Vec<&Path> = [system.as_path(), user.as_path()]
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect();
+        if layers.is_empty() {
+            // Neither exists; report the missing user file as before
+            return Self::load_from(&user);
+        }
+        Self::load_layers(&layers)
+    }
+
+    /// Merge config files in order, later files winning. Tables such as `[ui]` are merged
+    /// key by key, while arrays and plain values are replaced as a whole
+    pub fn load_layers(paths: &[&Path]) -> Result<Self> {
+        let Some(last) = paths.last() else {
+            anyhow::bail!("No config files to load");
+        };
+
+        let mut merged = toml::Table::new();
+        for path in paths {
+            let contents = fs::read_to_string(path)?;
+            let layer: toml::Table = toml::from_str(&contents)
+                .map_err(|e| ConfigParseError::from_toml(path, &contents, &e))?;
+            // Check each file over the defaults first, so a bad value is reported against
+            // the file it is in. Required keys may come from another layer
+            let mut alone = toml::Table::try_from(Self::default())?;
+            merge_tables(&mut alone, layer.clone());
+            Self::from_table(alone, path)?;
+            merge_tables(&mut merged, layer);
+        }
+        Self::from_table(merged, last)
+    }
+
+    fn from_table(table: toml::Table, path: &Path) -> Result<Self> {
+        let invalid = |message: String| ConfigParseError {
+            path: path.to_path_buf(),
+            line: None,
+            column: None,
+            message,
+        };
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| invalid(e.message().to_string()))?;
+        config
+            .validate()
+            .map_err(|invalid| ConfigValidationError::new(path, invalid))?;
         Ok(config)
     }
 
+    /// Names of the profiles defined in the config file
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+
+    /// This config with the named profile's settings layered on top
+    pub fn with_profile(&self, name: &str) -> Result<Self> {
+        let Some(overrides) = self.profiles.get(name) else {
+            anyhow::bail!(
+                "Unknown profile '{}'. Available profiles: {}",
+                name,
+                self.profile_names().join(", ")
+            );
+        };
+
+        let mut merged = toml::Table::try_from(self)?;
+        merged.remove("profiles");
+        merged.extend(overrides.clone());
+
+        let mut config: Self = toml::Value::Table(merged)
+            .try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid settings in profile '{}': {}", name, e))?;
+        config
+            .validate()
+            .map_err(|(_, e)| anyhow::anyhow!("Invalid settings in profile '{}': {}", name, e))?;
+        config.profiles = self.profiles.clone();
+        config.active_profile = Some(name.to_string());
+        Ok(config)
+    }
+
+    /// Use `key` for this run only (`--api-key`), instead of the stored key or
+    /// `api_key_command`
+    pub fn with_api_key_override(mut self, key: String) -> Self {
+        self.stored_api_key.get_or_insert(std::mem::take(&mut self.api_key));
+        self.api_key = key;
+        self
+    }
+
+    /// Replace `api_key` with the output of `api_key_command`, when one is configured
+    pub fn resolve_api_key(&mut self) -> Result<()> {
+        let Some(command) = self.api_key_command.as_deref() else {
+            return Ok(());
+        };
+
+        // The configured shell, else `cmd /C` on Windows and `sh -c` elsewhere
+        let shell = self.shell.as_deref().unwrap_or_default();
+        let output = crate::shell::shell_command(shell, command)
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to run api_key_command `{}`: {}", command, e))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "api_key_command `{}` failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if key.is_empty() {
+            anyhow::bail!("api_key_command `{}` printed nothing", command);
+        }
+        self.api_key = key;
+        Ok(())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let config: Self = toml::from_str(&contents)
+            .map_err(|e| ConfigParseError::from_toml(path, &contents, &e))?;
+
+        if let Err(invalid) = config.validate() {
+            return Err(ConfigValidationError::new(path, invalid).into());
+        }
+
+        Ok(config)
+    }
+
+    /// Range checks that TOML types alone can't express. Errors name the setting and
+    /// say what is wrong with it
+    fn validate(&self) -> std::result::Result<(), (&'static str, String)> {
+        if !MAX_SUGGESTIONS_RANGE.contains(&self.max_suggestions) {
+            return Err(("max_suggestions", format!(
+                "max_suggestions must be between {} and {}, got {}",
+                MAX_SUGGESTIONS_RANGE.start(),
+                MAX_SUGGESTIONS_RANGE.end(),
+                self.max_suggestions
+            )));
+        }
+
+        if self.cache_ttl_secs == 0 {
+            return Err((
+                "cache_ttl_secs",
+                "cache_ttl_secs must be at least 1; set cache_answers = false to turn caching off"
+                    .to_string(),
+            ));
+        }
+
+        if self.fallback_models.iter().any(|model| model.trim().is_empty()) {
+            return Err((
+                "fallback_models",
+                "fallback_models must not contain empty model names".to_string(),
+            ));
+        }
+
+        if self.max_concurrent_requests == 0 {
+            return Err((
+                "max_concurrent_requests",
+                "max_concurrent_requests must be at least 1".to_string(),
+            ));
+        }
+
+        for (key, shell) in [("shell", &self.shell), ("shell_type", &self.shell_type)] {
+            if let Some(shell) = shell.as_deref() {
+                if !crate::shell::SUPPORTED_SHELLS.contains(&shell) {
+                    return Err((key, format!(
+                        "{} must be one of {}, got \"{}\"",
+                        key,
+                        crate::shell::SUPPORTED_SHELLS.join(", "),
+                        shell
+                    )));
+                }
+            }
+        }
+
+        if self.stop.len() > crate::api::MAX_STOP_SEQUENCES {
+            return Err(("stop", format!(
+                "stop allows at most {} sequences, got {}",
+                crate::api::MAX_STOP_SEQUENCES,
+                self.stop.len()
+            )));
+        }
+        if self.stop.iter().any(|stop| stop.is_empty()) {
+            return Err(("stop", "stop sequences must not be empty".to_string()));
+        }
+
+        if self.ui.prompt_indicator.trim().is_empty() {
+            return Err(("ui.prompt_indicator", "ui.prompt_indicator must not be empty".to_string()));
+        }
+        if self.ui.menu_prompt.trim().is_empty() {
+            return Err(("ui.menu_prompt", "ui.menu_prompt must not be empty".to_string()));
+        }
+        if self.ui.menu_default == MenuDefault::First && !self.ui.allow_run_default {
+            return Err((
+                "ui.menu_default",
+                "ui.menu_default = \"first\" runs a command on Enter; set ui.allow_run_default = true to allow it"
+                    .to_string(),
+            ));
+        }
+
+        for rule in &self.follow_up_rules {
+            for pattern in std::iter::once(&rule.command).chain(rule.output.as_ref()) {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    return Err(("follow_up_rules", format!(
+                        "follow_up_rules pattern \"{}\" is not a valid regex: {}",
+                        pattern, e
+                    )));
+                }
+            }
+        }
+
+        if let Some(user_agent) = self.user_agent.as_deref() {
+            if user_agent.trim().is_empty()
+                || reqwest::header::HeaderValue::from_str(user_agent).is_err()
+            {
+                return Err(("user_agent", format!(
+                    "user_agent must be a non-empty single-line header value, got \"{}\"",
+                    user_agent.escape_default()
+                )));
+            }
+        }
+
+        if let Some(url) = self.prompt_url.as_deref() {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => {
+                    return Err((
+                        "prompt_url",
+                        format!("prompt_url must be an http(s) URL, got \"{}\"", url),
+                    ))
+                }
+            }
+        }
+
+        if let Some(proxy) = self.proxy.as_deref() {
+            if let Err(e) = reqwest::Proxy::all(proxy) {
+                return Err((
+                    "proxy",
+                    format!("proxy is not a valid URL (\"{}\"): {}", proxy, e),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_dir = Self::config_dir()?;
         fs::create_dir_all(&config_dir)?;
 
+        let stored = self.for_disk().without_project()?;
         let path = Self::config_path()?;
-        let contents = toml::to_string_pretty(self)?;
-        fs::write(path, contents)?;
+        let mut contents = match &self.active_profile {
+            Some(name) => stored.fold_into_profile(name)?,
+            None => toml::Table::try_from(&stored)?,
+        };
+
+        // Leave settings that come from the system config out of the user's file, so later
+        // changes to the baseline still reach them
+        let system = Self::system_config_path();
+        if system.exists() {
+            let system: toml::Table = toml::from_str(&fs::read_to_string(&system)?)?;
+            let written = match fs::read_to_string(&path) {
+                Ok(contents) => toml::from_str(&contents).unwrap_or_default(),
+                Err(_) => toml::Table::new(),
+            };
+            strip_inherited(&mut contents, &system, &written);
+        }
+
+        fs::write(path, toml::to_string_pretty(&contents)?)?;
         Ok(())
     }
 
+    /// What `save` writes: keys that only live for this run are left out
+    pub(crate) fn for_disk(&self) -> Self {
+        let mut stored = self.clone();
+        if let Some(key) = &self.stored_api_key {
+            stored.api_key = key.clone();
+        }
+        // Never write a key fetched from a secret manager back to disk
+        if stored.api_key_command.is_some() {
+            stored.api_key = String::new();
+        }
+        stored
+    }
+
+    /// Write settings that differ from the file's top level into the active profile,
+    /// so changing the model under `--profile work` doesn't touch other profiles
+    fn fold_into_profile(&self, name: &str) -> Result<toml::Table> {
+        let mut base = toml::Table::try_from(Self::load_base()?)?;
+        let current = toml::Table::try_from(self)?;
+
+        let mut profile = self.profiles.get(name).cloned().unwrap_or_default();
+        for (key, value) in current {
+            if key != "profiles" && base.get(&key) != Some(&value) {
+                profile.insert(key, value);
+            }
+        }
+
+        let mut profiles = self.profiles.clone();
+        profiles.insert(name.to_string(), profile);
+        base.insert("profiles".to_string(), toml::Table::try_from(profiles)?.into());
+        Ok(base)
+    }
+
+    /// Whether a user or system config file is present
     pub fn exists() -> bool {
         Self::config_path().map(|p| p.exists()).unwrap_or(false)
+            || Self::system_config_path().exists()
+    }
+}
+
+/// Layer `overrides` onto `base`: nested tables merge key by key, anything else replaces
+pub fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(nested)) => {
+                merge_tables(existing, nested)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Drop entries of `table` that only repeat what `inherited` provides, keeping any that
+/// `written` (the file being replaced) already set explicitly
+pub fn strip_inherited(table: &mut toml::Table, inherited: &toml::Table, written: &toml::Table) {
+    let empty = toml::Table::new();
+    table.retain(|key, value| {
+        let Some(base) = inherited.get(key) else {
+            return true;
+        };
+        match (value, base) {
+            (toml::Value::Table(nested), toml::Value::Table(base)) => {
+                let nested_written =
+                    written.get(key).and_then(toml::Value::as_table).unwrap_or(&empty);
+                strip_inherited(nested, base, nested_written);
+                !nested.is_empty() || written.contains_key(key)
+            }
+            (value, base) => value != base || written.contains_key(key),
+        }
+    });
+}
+
+/// What a built-in model is best at, shown when choosing one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelCategory {
+    FastAndCheap,
+    BestReasoning,
+    LongContext,
+}
+
+impl ModelCategory {
+    pub fn label(self) -> &'static str {
+        match self {
+            ModelCategory::FastAndCheap => "fast & cheap",
+            ModelCategory::BestReasoning => "best reasoning",
+            ModelCategory::LongContext => "long context",
+        }
+    }
+}
+
+/// A model offered in `shy init` and `/model`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelInfo {
+    pub id: &'static str,
+    pub category: ModelCategory,
+    pub blurb: &'static str,
+}
+
+impl ModelInfo {
+    /// One aligned picker line: use case, id and what it's good for
+    pub fn menu_label(&self) -> String {
+        format!("{:<15} {:<28} {}", self.category.label(), self.id, self.blurb)
     }
 }
 
-pub const AVAILABLE_MODELS: &[&str] = &[
-    "openai/gpt-4o-mini",
-    "openai/gpt-4o",
-    "openai/o4-mini",
-    "google/gemini-2.5-flash",
-    "google/gemini-2.5-pro",
-    "anthropic/claude-3-5-sonnet",
+/// Built-in models, grouped by use case; the first is the default
+pub const AVAILABLE_MODELS: &[ModelInfo] = &[
+    ModelInfo {
+        id: "openai/gpt-4o-mini",
+        category: ModelCategory::FastAndCheap,
+        blurb: "Quick everyday commands for a fraction of a cent",
+    },
+    ModelInfo {
+        id: "google/gemini-2.5-flash",
+        category: ModelCategory::FastAndCheap,
+        blurb: "Very fast answers, good at one-liners",
+    },
+    ModelInfo {
+        id: "openai/o4-mini",
+        category: ModelCategory::BestReasoning,
+        blurb: "Thinks before answering; tricky pipelines and scripts",
+    },
+    ModelInfo {
+        id: "anthropic/claude-3-5-sonnet",
+        category: ModelCategory::BestReasoning,
+        blurb: "Careful explanations and safe suggestions",
+    },
+    ModelInfo {
+        id: "openai/gpt-4o",
+        category: ModelCategory::BestReasoning,
+        blurb: "Strong all-rounder for harder questions",
+    },
+    ModelInfo {
+        id: "google/gemini-2.5-pro",
+        category: ModelCategory::LongContext,
+        blurb: "Reads large files and long histories",
+    },
 ];
+
+/// Picker lines for `AVAILABLE_MODELS`, in the same order
+pub fn model_menu_items() -> Vec<String> {
+    AVAILABLE_MODELS.iter().map(ModelInfo::menu_label).collect()
+}
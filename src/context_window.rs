@@ -0,0 +1,90 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::api::OpenRouterClient;
+use crate::config::Config;
+
+/// How long fetched context limits are trusted before the model list is fetched again
+pub const LIMITS_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Rough token count for text sent to the model: about four characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Tokens the request may use, leaving a quarter of the window for the answer
+pub fn request_budget(context_limit: usize) -> usize {
+    context_limit - context_limit / 4
+}
+
+/// How many referenced files and history entries fit in `budget` next to the `fixed`
+/// part (instructions, environment and question). Files are dropped first, last one
+/// first, then the oldest history entries. Returns the counts to keep
+pub fn fit_context(fixed: usize, files: &[usize], history: &[usize], budget: usize) -> (usize, usize) {
+    let mut kept_files = files.len();
+    let mut kept_history = history.len();
+    let total = |kept_files: usize, kept_history: usize| {
+        fixed
+            + files[..kept_files].iter().sum::<usize>()
+            + history[history.len() - kept_history..].iter().sum::<usize>()
+    };
+
+    while kept_files > 0 && total(kept_files, kept_history) > budget {
+        kept_files -= 1;
+    }
+    while kept_history > 0 && total(kept_files, kept_history) > budget {
+        kept_history -= 1;
+    }
+    (kept_files, kept_history)
+}
+
+/// Context window of each model, from the last model list fetched
+pub fn cache_path() -> Result<PathBuf> {
+    Ok(Config::config_dir()?.join("cache").join("context_limits.json"))
+}
+
+/// Context limits for a session: the cached list while it is younger than
+/// `LIMITS_MAX_AGE`, even when it doesn't name the model, otherwise a fresh one from
+/// OpenRouter. A failed fetch keeps the old list (or an empty one) for another
+/// `LIMITS_MAX_AGE`, so an unreachable API doesn't slow down every startup
+pub async fn load(client: &OpenRouterClient, path: &Path) -> Option<BTreeMap<String, usize>> {
+    if let Some(limits) = load_limits(path, LIMITS_MAX_AGE) {
+        return Some(limits);
+    }
+    // Other endpoints don't serve OpenRouter's model list
+    if !client.uses_openrouter() {
+        return None;
+    }
+
+    let limits = match client.context_limits().await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            log::debug!("Could not fetch model context limits: {}", e);
+            load_limits(path, Duration::MAX).unwrap_or_default()
+        }
+    };
+    if let Err(e) = save_limits(path, &limits) {
+        log::warn!("Could not cache model context limits: {}", e);
+    }
+    Some(limits)
+}
+
+/// Cached limits, unless the file is older than `max_age`
+pub fn load_limits(path: &Path, max_age: Duration) -> Option<BTreeMap<String, usize>> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    if SystemTime::now().duration_since(modified).unwrap_or_default() >= max_age {
+        return None;
+    }
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+pub fn save_limits(path: &Path, limits: &BTreeMap<String, usize>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string(limits)?)?;
+    Ok(())
+}
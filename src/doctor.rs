@@ -0,0 +1,311 @@
+use crate::api::{ApiError, OpenRouterClient};
+use crate::config::{Config, ConfigParseError, ConfigValidationError};
+use crate::repl::{history_search_order, ShyRepl};
+use anyhow::Result;
+use console::{style, Color};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Outcome of a single diagnostic check
+struct Check {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn print(&self) {
+        let mark = if self.passed {
+            style("✓").fg(Color::Green)
+        } else {
+            style("✗").fg(Color::Red)
+        };
+        println!(
+            "  {} {}  {}",
+            mark,
+            style(self.name).bold(),
+            style(&self.detail).dim()
+        );
+        if let Some(fix) = &self.fix {
+            println!("      {} {}", style("fix:").fg(Color::Yellow), fix);
+        }
+    }
+}
+
+pub async fn run_doctor() -> Result<()> {
+    println!();
+    println!("{}", style("Shy Doctor").bold().fg(Color::Cyan));
+    println!();
+
+    let mut checks = Vec::new();
+    let config = check_config(&mut checks);
+
+    if let Some(config) = &config {
+        check_api(config, &mut checks).await;
+    }
+
+    check_shell(&mut checks, config.as_ref());
+
+    for check in &checks {
+        check.print();
+    }
+
+    let failures = checks.iter().filter(|check| !check.passed).count();
+    println!();
+    if failures == 0 {
+        println!("{} Everything looks good!", style("✓").fg(Color::Green));
+    } else {
+        println!(
+            "{} {} check{} failed",
+            style("✗").fg(Color::Red),
+            failures,
+            if failures == 1 { "" } else { "s" }
+        );
+    }
+    println!();
+
+    Ok(())
+}
+
+fn check_config(checks: &mut Vec<Check>) -> Option<Config> {
+    let path = match Config::config_path() {
+        Ok(path) => path,
+        Err(e) => {
+            checks.push(Check::fail(
+                "Config file",
+                e.to_string(),
+                "Set HOME so a config directory can be found",
+            ));
+            return None;
+        }
+    };
+
+    let system = Config::system_config_path();
+    if !path.exists() && !system.exists() {
+        checks.push(Check::fail(
+            "Config file",
+            format!("{} not found", path.display()),
+            "Run `shy init`",
+        ));
+        return None;
+    }
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            let invalid = e.downcast_ref::<ConfigValidationError>();
+            let fix = match e.downcast_ref::<ConfigParseError>() {
+                Some(_) => "Fix the syntax error or run `shy init` to recreate the file",
+                None if invalid.is_some() => "Change that setting in the file named above",
+                None if e.to_string().contains("api_key_command") => {
+                    "Check that api_key_command works when run on its own"
+                }
+                None => "Check the file permissions or run `shy init`",
+            };
+            checks.push(Check::fail("Config file", e.to_string(), fix));
+            return None;
+        }
+    };
+
+    let files: Vec<String> = [system.as_path(), path.as_path()]
+        .into_iter()
+        .filter(|file| file.exists())
+        .map(|file| file.display().to_string())
+        .collect();
+    checks.push(Check::pass("Config file", files.join(" + ")));
+
+    if config.api_key.trim().is_empty() {
+        checks.push(Check::fail(
+            "API key",
+            "No API key configured",
+            "Run `shy init` and paste your OpenRouter key",
+        ));
+        return None;
+    }
+
+    Some(config)
+}
+
+async fn check_api(config: &Config, checks: &mut Vec<Check>) {
+    let client = OpenRouterClient::new(config.api_key.clone(), config.default_model.clone())
+        .with_timeout(Duration::from_secs(10))
+        .with_proxy(config.proxy.as_deref(), config.danger_accept_invalid_certs)
+        .with_user_agent(config.user_agent.as_deref());
+
+    match client.check_api_key().await {
+        Ok(()) => checks.push(Check::pass("API key", "Authenticated with OpenRouter")),
+        Err(e) if e.is::<reqwest::Error>() => {
+            checks.push(Check::fail(
+                "Network",
+                format!("Could not reach openrouter.ai: {}", e),
+                "Check your internet connection, or set `proxy` in the config file",
+            ));
+            return;
+        }
+        Err(e) => {
+            // The fix column already says what to do, so skip the error's own hint
+            let detail = match e.downcast_ref::<ApiError>() {
+                Some(api_error) => format!("{} ({})", api_error.message, api_error.status),
+                None => e.to_string(),
+            };
+            checks.push(Check::fail(
+                "API key",
+                detail,
+                "Create a new key at https://openrouter.ai/keys and run `shy init`",
+            ));
+            return;
+        }
+    }
+
+    match client.list_models().await {
+        Ok(models) if models.contains(&config.default_model) => {
+            checks.push(Check::pass("Model", config.default_model.clone()))
+        }
+        Ok(_) => checks.push(Check::fail(
+            "Model",
+            format!("{} is not offered by OpenRouter", config.default_model),
+            "Pick another model with `/model` or `shy init`",
+        )),
+        Err(e) => checks.push(Check::fail(
+            "Model",
+            format!("Could not fetch the model list: {}", e),
+            "Try again later",
+        )),
+    }
+}
+
+fn check_shell(checks: &mut Vec<Check>, config: Option<&Config>) {
+    if let Some(shell) = config.and_then(|config| config.shell_type.as_deref()) {
+        checks.push(Check::pass("Shell", format!("{} (shell_type in config)", shell)));
+    } else {
+        let shell = ShyRepl::detect_current_shell();
+        if shell == "unknown" {
+            checks.push(Check::fail(
+                "Shell",
+                "Could not detect the current shell",
+                "Set shell_type in config.toml, or the SHELL environment variable",
+            ));
+        } else {
+            checks.push(Check::pass("Shell", shell));
+        }
+    }
+
+    let existing: Vec<String> = ShyRepl::collect_all_history_paths()
+        .into_iter()
+        .filter(|(path, _)| path.exists())
+        .map(|(path, shell_type)| format!("{} ({})", shell_type, path.display()))
+        .collect();
+
+    if existing.is_empty() {
+        checks.push(Check::fail(
+            "History",
+            "No shell history files found",
+            "Set HISTFILE, or enable history in your shell",
+        ));
+    } else {
+        checks.push(Check::pass("History", existing.join(", ")));
+    }
+}
+
+/// `shy doctor --list-shells`: every history file shy knows about, whether it exists,
+/// and which one would be read
+pub fn list_history_sources() -> Result<()> {
+    // A missing or broken config just means no history file was chosen
+    let config = Config::load().ok();
+    let chosen = config.as_ref().and_then(|config| config.history_file.clone());
+    let current_shell = config
+        .as_ref()
+        .map_or_else(ShyRepl::detect_current_shell, ShyRepl::shell_type);
+    let all_paths = ShyRepl::collect_all_history_paths();
+    let used = history_search_order(all_paths.clone(), chosen.as_deref(), &current_shell)
+        .into_iter()
+        .map(|(path, _)| path)
+        .find(|path| path.exists());
+
+    println!();
+    println!(
+        "{} {}",
+        style("History sources").bold().fg(Color::Cyan),
+        style(format!("(current shell: {})", current_shell)).dim()
+    );
+    match &chosen {
+        Some(path) => println!(
+            "  {} {}",
+            style("history_file in config:").fg(Color::Green),
+            path.display()
+        ),
+        None => println!("  {}", style("No history_file in config, auto-detecting").dim()),
+    }
+    println!();
+
+    let mut listed = all_paths;
+    if let Some(path) = chosen.filter(|chosen| !listed.iter().any(|(path, _)| path == chosen)) {
+        listed.insert(0, (path, "Custom"));
+    }
+    for (path, shell_type) in &listed {
+        let (mark, detail) = match describe_history_file(path) {
+            Some(detail) => (style("✓").fg(Color::Green), detail),
+            None => (style("✗").fg(Color::Red), "not found".to_string()),
+        };
+        let marker = if used.as_ref() == Some(path) {
+            format!("  {}", style("← used").bold().fg(Color::Green))
+        } else {
+            String::new()
+        };
+        println!(
+            "  {} {:<10} {}  {}{}",
+            mark,
+            shell_type,
+            path.display(),
+            style(detail).dim(),
+            marker
+        );
+    }
+
+    if used.is_none() {
+        println!();
+        println!(
+            "  {} No history file exists; set HISTFILE or enable history in your shell",
+            style("fix:").fg(Color::Yellow)
+        );
+    }
+    println!();
+    Ok(())
+}
+
+/// Line count and last change of a history file, `None` if it doesn't exist
+fn describe_history_file(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let lines = fs::read(path)
+        .map(|contents| contents.iter().filter(|&&b| b == b'\n').count())
+        .unwrap_or(0);
+    let modified = metadata
+        .modified()
+        .map(|time| {
+            chrono::DateTime::<chrono::Local>::from(time)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|_| "unknown".to_string());
+    Some(format!("{} lines, modified {}", lines, modified))
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Good,
+    Bad,
+}
+
+/// One `/good` or `/bad`, kept locally for comparing models later
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Feedback {
+    pub timestamp: String,
+    pub model: String,
+    pub question: String,
+    pub response: String,
+    pub rating: Rating,
+}
+
+impl Feedback {
+    pub fn new(model: &str, question: &str, response: &str, rating: Rating) -> Self {
+        Self {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            model: model.to_string(),
+            question: question.to_string(),
+            response: response.to_string(),
+            rating,
+        }
+    }
+}
+
+/// Ratings for one model
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tally {
+    pub good: usize,
+    pub bad: usize,
+}
+
+impl Tally {
+    pub fn good_percent(&self) -> usize {
+        match self.good + self.bad {
+            0 => 0,
+            total => self.good * 100 / total,
+        }
+    }
+}
+
+pub fn feedback_path() -> Result<PathBuf> {
+    Ok(Config::config_dir()?.join("feedback.jsonl"))
+}
+
+pub fn append(path: &Path, feedback: &Feedback) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(feedback)?)?;
+    Ok(())
+}
+
+/// Good and bad counts per model, skipping lines that don't parse
+pub fn summarize(path: &Path) -> Result<BTreeMap<String, Tally>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut tallies: BTreeMap<String, Tally> = BTreeMap::new();
+    for feedback in content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Feedback>(line).ok())
+    {
+        let tally = tallies.entry(feedback.model).or_default();
+        match feedback.rating {
+            Rating::Good => tally.good += 1,
+            Rating::Bad => tally.bad += 1,
+        }
+    }
+    Ok(tallies)
+}
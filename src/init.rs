@@ -1,33 +1,48 @@
-use crate::config::{Config, AVAILABLE_MODELS};
+use crate::config::{model_menu_items, Config, ConfigParseError, AVAILABLE_MODELS};
+use crate::repl::cancellable;
 use anyhow::Result;
-use dialoguer::{theme::ColorfulTheme, Input, Select};
+use console::{style, Color};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
 
-pub fn run_init() -> Result<()> {
+/// Ask for a key and a model and save them. Returns false, with nothing written, when
+/// the user presses Ctrl-C at a prompt
+pub fn run_init() -> Result<bool> {
     println!("🎯 Welcome to Shy - AI Shell Assistant Setup");
     println!();
 
     // Get API key
-    let api_key: String = Input::with_theme(&ColorfulTheme::default())
+    let api_key = Input::<String>::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter your OpenRouter API key")
-        .interact_text()?;
+        .interact_text();
+    let Some(api_key) = cancellable(api_key)? else {
+        return Ok(setup_cancelled());
+    };
 
     if api_key.trim().is_empty() {
         anyhow::bail!("API key cannot be empty");
     }
 
-    // Select model
+    // Select model, grouped by what each one is good at
+    println!(
+        "{}",
+        style("Not sure? Start with a fast & cheap model; switch any time with /model.").dim()
+    );
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Choose your default AI model")
         .default(0)
-        .items(AVAILABLE_MODELS)
-        .interact()?;
+        .items(&model_menu_items())
+        .interact();
+    let Some(selection) = cancellable(selection)? else {
+        return Ok(setup_cancelled());
+    };
 
-    let default_model = AVAILABLE_MODELS[selection].to_string();
+    let default_model = AVAILABLE_MODELS[selection].id.to_string();
 
     // Create and save config
     let config = Config {
         api_key: api_key.trim().to_string(),
         default_model,
+        ..Default::default()
     };
 
     config.save()?;
@@ -39,5 +54,55 @@ pub fn run_init() -> Result<()> {
     println!();
     println!("You can now run 'shy' to start the AI assistant.");
 
-    Ok(())
+    Ok(true)
+}
+
+fn setup_cancelled() -> bool {
+    println!("{}", style("Setup cancelled; no configuration was written.").fg(Color::Yellow));
+    false
+}
+
+/// Explain a malformed config file and offer to recreate it.
+/// Returns true if a fresh config was written.
+pub fn recover_from_parse_error(error: &ConfigParseError) -> Result<bool> {
+    eprintln!(
+        "{} Could not read your configuration file.",
+        style("✗").fg(Color::Red)
+    );
+    eprintln!(
+        "  {}: {}",
+        style("File").fg(Color::Green),
+        style(error.path.display()).fg(Color::White)
+    );
+    if let (Some(line), Some(column)) = (error.line, error.column) {
+        eprintln!(
+            "  {}: line {}, column {}",
+            style("Location").fg(Color::Green),
+            style(line).fg(Color::White),
+            style(column).fg(Color::White)
+        );
+    }
+    eprintln!(
+        "  {}: {}",
+        style("Problem").fg(Color::Green),
+        style(&error.message).fg(Color::Red)
+    );
+    eprintln!();
+
+    let recreate = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Recreate the configuration now? (the broken file will be overwritten)")
+        .default(false)
+        .interact();
+    let recreate = cancellable(recreate)?.unwrap_or(false);
+
+    if !recreate {
+        println!(
+            "{}",
+            style("Fix the file by hand or run 'shy init' to start over.").dim()
+        );
+        return Ok(false);
+    }
+
+    println!();
+    run_init()
 }
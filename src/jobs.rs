@@ -0,0 +1,268 @@
+use anyhow::Result;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A command started with `/run --bg`, writing its output to a log file
+pub struct BackgroundJob {
+    pub pid: u32,
+    pub command: String,
+    pub log_path: PathBuf,
+    pub started: Instant,
+    child: Child,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    /// Finished, with its exit code unless a signal ended it
+    Exited(Option<i32>),
+}
+
+impl BackgroundJob {
+    /// Start `process` without a terminal: no stdin, output to a temporary log file.
+    /// On Unix it gets its own process group so Ctrl-C in the REPL leaves it alone
+    /// and `/kill` can stop everything it started
+    pub fn spawn(mut process: Command, command: &str) -> Result<Self> {
+        let (log, log_path) = tempfile::Builder::new()
+            .prefix("shy-job-")
+            .suffix(".log")
+            .tempfile()?
+            .keep()?;
+
+        process
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log);
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            process.process_group(0);
+        }
+
+        let child = process.spawn()?;
+        Ok(Self {
+            pid: child.id(),
+            command: command.to_string(),
+            log_path,
+            started: Instant::now(),
+            child,
+        })
+    }
+
+    pub fn status(&mut self) -> JobStatus {
+        match self.child.try_wait() {
+            Ok(Some(status)) => JobStatus::Exited(status.code()),
+            Ok(None) => JobStatus::Running,
+            // Can't be waited on any more, so it isn't ours to manage
+            Err(_) => JobStatus::Exited(None),
+        }
+    }
+
+    /// Ask the job to stop, forcing it after a couple of seconds
+    pub fn terminate(&mut self) -> Result<()> {
+        if self.status() != JobStatus::Running {
+            return Ok(());
+        }
+
+        self.signal_group("-TERM");
+        for _ in 0..20 {
+            if self.status() != JobStatus::Running {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        self.signal_group("-KILL");
+        self.child.kill().ok();
+        self.child.wait()?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn signal_group(&mut self, signal: &str) {
+        let group = format!("-{}", self.pid);
+        let sent = Command::new("kill")
+            .args([signal, "--", &group])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !sent {
+            self.child.kill().ok();
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn signal_group(&mut self, _signal: &str) {
+        self.child.kill().ok();
+    }
+}
+
+/// What a foreground command printed, and whether it was stopped for taking too long
+pub struct TimedOutput {
+    pub output: Output,
+    /// stdout and stderr interleaved in the order they arrived
+    pub combined: Vec<u8>,
+    pub timed_out: bool,
+}
+
+/// Which pipe a chunk of output came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Like `Command::output`, but stops the command and everything it started once
+/// `timeout` passes. It stays in the REPL's process group so it can still prompt on
+/// the terminal (a separate group would be stopped by SIGTTIN), which is why the
+/// whole process tree is signalled rather than a group.
+///
+/// Each chunk is handed to `on_output` as soon as it is read so progress can be shown
+/// live. Chunks are raw reads rather than lines, so `\r` progress bars update in place
+pub fn run_with_timeout(
+    mut process: Command,
+    timeout: Option<Duration>,
+    mut on_output: impl FnMut(OutputStream, &[u8]),
+) -> io::Result<TimedOutput> {
+    let mut child = process.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // Drain both pipes while waiting, or a chatty command blocks on a full pipe
+    let (sender, chunks) = mpsc::channel();
+    forward_chunks(child.stdout.take(), OutputStream::Stdout, sender.clone());
+    forward_chunks(child.stderr.take(), OutputStream::Stderr, sender);
+
+    let mut output = Output {
+        status: Default::default(),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+    let mut combined = Vec::new();
+    let mut receive = |stream: OutputStream, chunk: Vec<u8>| {
+        on_output(stream, &chunk);
+        combined.extend_from_slice(&chunk);
+        match stream {
+            OutputStream::Stdout => output.stdout.extend(chunk),
+            OutputStream::Stderr => output.stderr.extend(chunk),
+        }
+    };
+
+    let started = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+        match chunks.recv_timeout(Duration::from_millis(20)) {
+            Ok((stream, chunk)) => receive(stream, chunk),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // Both pipes closed, usually because the command is exiting
+            Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_millis(20)),
+        }
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            timed_out = true;
+            terminate_tree(&mut child);
+            break child.wait()?;
+        }
+    };
+    // Whatever was still in the pipes when the command exited. A background process
+    // the tree walk missed (`(sleep 1000 &)`, `nohup server &`) can hold them open
+    // forever, so only wait briefly for the rest rather than for both to close
+    let deadline = Instant::now() + Duration::from_millis(300);
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match chunks.recv_timeout(remaining) {
+            Ok((stream, chunk)) => receive(stream, chunk),
+            Err(_) => break,
+        }
+    }
+    drop(chunks);
+
+    output.status = status;
+    Ok(TimedOutput {
+        output,
+        combined,
+        timed_out,
+    })
+}
+
+fn forward_chunks(
+    pipe: Option<impl Read + Send + 'static>,
+    stream: OutputStream,
+    sender: mpsc::Sender<(OutputStream, Vec<u8>)>,
+) {
+    let Some(mut pipe) = pipe else {
+        return;
+    };
+    thread::spawn(move || {
+        let mut buffer = [0; 8192];
+        loop {
+            match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => {
+                    if sender.send((stream, buffer[..read].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+/// SIGTERM the child and its descendants, then SIGKILL whatever is left after 2s
+#[cfg(unix)]
+fn terminate_tree(child: &mut Child) {
+    let mut pids = vec![child.id()];
+    let mut next = 0;
+    while next < pids.len() {
+        pids.extend(child_pids(pids[next]));
+        next += 1;
+    }
+
+    let signal = |signal: &str| {
+        for pid in &pids {
+            Command::new("kill")
+                .args([signal, &pid.to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .ok();
+        }
+    };
+
+    signal("-TERM");
+    for _ in 0..20 {
+        if child.try_wait().is_ok_and(|status| status.is_some()) {
+            // The shell went quietly; make sure nothing it started lingers
+            signal("-KILL");
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    signal("-KILL");
+    child.kill().ok();
+}
+
+#[cfg(not(unix))]
+fn terminate_tree(child: &mut Child) {
+    child.kill().ok();
+}
+
+#[cfg(unix)]
+fn child_pids(pid: u32) -> Vec<u32> {
+    Command::new("pgrep")
+        .args(["-P", &pid.to_string()])
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
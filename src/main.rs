@@ -1,67 +1,368 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{CommandFactory, Parser, Subcommand};
 use clap_complete::{generate, Generator, Shell};
 use std::io;
+use std::path::PathBuf;
 
 mod api;
+mod cache;
+mod command_log;
 mod config;
+mod context_window;
+mod doctor;
+mod feedback;
 mod init;
+mod jobs;
+mod placeholders;
+mod preview;
+mod project;
+mod redact;
 mod repl;
+mod safety;
+mod session;
+mod shared_prompt;
+mod shell;
+mod tools;
+mod undo;
 
-use config::Config;
-use init::run_init;
+use config::{Config, ConfigParseError};
+use init::{recover_from_parse_error, run_init};
 use repl::ShyRepl;
 
 #[derive(Parser)]
 #[command(name = "shy")]
 #[command(about = "AI-powered shell assistant")]
 #[command(version)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Ask a single question instead of starting the REPL
+    prompt: Vec<String>,
+
+    /// Print the context and payload that would be sent, without calling the API
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Print only the model's response: no spinner, timing or headers
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Log the request payload and raw streamed chunks to stderr (also SHY_DEBUG=1)
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Run commands without asking for confirmation. This bypasses the safety prompt
+    /// for everything except commands flagged as dangerous (rm -rf, mkfs, ...)
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Always ask the API, even when `cache_answers` is on
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Use the named profile from the config file
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// OpenRouter API key for this run only; it is never written to the config file
+    #[arg(long, global = true, value_name = "KEY")]
+    api_key: Option<String>,
+
+    /// Shell that runs suggested commands (default: the detected shell)
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(shell::SUPPORTED_SHELLS))]
+    shell: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize configuration (API key and model selection)
     Init,
+    /// Check configuration, API access and shell history detection
+    Doctor {
+        /// List every candidate history file and which one would be read
+        #[arg(long)]
+        list_shells: bool,
+    },
+    /// Ask for a command, show the best suggestion, confirm once and run it
+    Exec {
+        /// What you want done, e.g. "compress this folder into a tarball"
+        #[arg(required = true)]
+        request: Vec<String>,
+    },
     /// Generate shell completions
     Completions {
-        /// The shell to generate completions for
+        /// The shell to generate completions for (default: the detected shell)
         #[arg(value_enum)]
-        shell: Shell,
+        shell: Option<Shell>,
+        /// Write the script where the shell loads completions from instead of printing it
+        #[arg(long)]
+        install: bool,
     },
 }
 
+/// Quiet mode is implied when stdout is redirected
+fn is_quiet(cli: &Cli) -> bool {
+    cli.quiet || !console::Term::stdout().is_term()
+}
+
+/// Debug logs go to stderr so they never mix with piped responses
+fn init_logging(verbose: bool) {
+    let debug_env = std::env::var("SHY_DEBUG").is_ok_and(|v| !v.is_empty() && v != "0");
+    let level = if verbose || debug_env {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Warn
+    };
+
+    env_logger::Builder::new()
+        .filter_module("shy", level)
+        .target(env_logger::Target::Stderr)
+        .format_timestamp_millis()
+        .init();
+}
+
+/// The `--profile` flag, the only profile, or an interactive pick among several
+fn choose_profile(config: &Config, requested: Option<&str>) -> Result<Option<String>> {
+    use dialoguer::{theme::ColorfulTheme, Select};
+
+    let names = config.profile_names();
+    if let Some(name) = requested {
+        return Ok(Some(name.to_string()));
+    }
+
+    match names.as_slice() {
+        [] => Ok(None),
+        [only] => Ok(Some(only.to_string())),
+        // Scripts can't answer a picker; they get the top-level settings
+        _ if !console::Term::stderr().is_term() => Ok(None),
+        _ => {
+            let selection = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Choose a profile")
+                .default(0)
+                .items(&names)
+                .interact()?;
+            Ok(Some(names[selection].to_string()))
+        }
+    }
+}
+
 fn print_completions<G: Generator>(gen: G, cmd: &mut clap::Command) {
     generate(gen, cmd, cmd.get_name().to_string(), &mut io::stdout());
 }
 
+/// Write the completion script to the shell's conventional location; print it instead
+/// when there is none
+fn install_completions(shell: Shell, cmd: &mut clap::Command) -> Result<()> {
+    let env_path = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let path = dirs::home_dir().and_then(|home| {
+        shell::completion_install_path(
+            shell,
+            &home,
+            env_path("XDG_CONFIG_HOME").as_deref(),
+            env_path("XDG_DATA_HOME").as_deref(),
+        )
+    });
+    let Some(path) = path else {
+        eprintln!("No standard completion directory for {shell}; printing the script instead.");
+        print_completions(shell, cmd);
+        return Ok(());
+    };
+
+    let mut script = Vec::new();
+    generate(shell, cmd, cmd.get_name().to_string(), &mut script);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create {}", parent.display()))?;
+    }
+    std::fs::write(&path, script).with_context(|| format!("Could not write {}", path.display()))?;
+
+    println!("Installed {shell} completions to {}", path.display());
+    match shell {
+        Shell::Zsh => println!(
+            "Add `fpath+=~/.zfunc` before `compinit` in ~/.zshrc if it isn't there yet, then open a new shell."
+        ),
+        _ => println!("Open a new shell to use them."),
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    init_logging(cli.verbose);
 
     match cli.command {
         Some(Commands::Init) => {
+            if cli.api_key.is_some() {
+                eprintln!("Warning: --api-key is ignored by `shy init`; the key entered here is saved.");
+            }
             run_init()?;
         }
-        Some(Commands::Completions { shell }) => {
+        Some(Commands::Doctor { list_shells: true }) => {
+            doctor::list_history_sources()?;
+        }
+        Some(Commands::Doctor { .. }) => {
+            doctor::run_doctor().await?;
+        }
+        Some(Commands::Completions { shell, install }) => {
+            let Some(shell) = shell.or_else(Shell::from_env) else {
+                anyhow::bail!("Could not detect your shell; name it, e.g. `shy completions zsh`");
+            };
             let mut cmd = Cli::command();
-            eprintln!("Generating completion file for {shell}...");
-            print_completions(shell, &mut cmd);
+            if install {
+                install_completions(shell, &mut cmd)?;
+            } else {
+                eprintln!("Generating completion file for {shell}...");
+                print_completions(shell, &mut cmd);
+            }
+        }
+        None if cli.dry_run && !Config::exists() => {
+            // A dry run never talks to the API, so it works without setup
+            let mut repl = ShyRepl::new(Config::default())?
+                .with_dry_run(true)
+                .with_quiet(is_quiet(&cli))
+                .with_shell(cli.shell.clone())
+                .with_assume_yes(cli.yes);
+            run_repl_or_prompt(&mut repl, &cli.prompt).await?;
+        }
+        Some(Commands::Exec { ref request }) if cli.dry_run && !Config::exists() => {
+            let mut repl = ShyRepl::new(Config::default())?
+                .with_dry_run(true)
+                .with_quiet(is_quiet(&cli))
+                .with_shell(cli.shell.clone());
+            repl.exec(&request.join(" ")).await?;
+        }
+        Some(Commands::Exec { ref request }) => {
+            let Some(config) = load_config(cli.profile.as_deref(), cli.api_key.clone())? else {
+                return Ok(());
+            };
+            let mut repl = ShyRepl::new(config)?
+                .with_dry_run(cli.dry_run)
+                .with_quiet(is_quiet(&cli))
+                .with_shell(cli.shell.clone())
+                .with_assume_yes(cli.yes)
+                .with_no_cache(cli.no_cache);
+            repl.exec(&request.join(" ")).await?;
         }
         None => {
             // No subcommand means start REPL
-            if !Config::exists() {
-                println!("Welcome to Shy! Let's set up your configuration first.");
-                run_init()?;
+            let Some(config) = load_config(cli.profile.as_deref(), cli.api_key.clone())? else {
+                return Ok(());
+            };
+            let mut repl = ShyRepl::new(config)?
+                .with_dry_run(cli.dry_run)
+                .with_quiet(is_quiet(&cli))
+                .with_shell(cli.shell.clone())
+                .with_assume_yes(cli.yes)
+                .with_no_cache(cli.no_cache);
+            run_repl_or_prompt(&mut repl, &cli.prompt).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Set up on first run, recover from a broken config file, apply the profile and
+/// resolve the API key. `None` when first-run setup was cancelled; declining to repair
+/// a broken config exits with status 1
+fn load_config(profile: Option<&str>, api_key: Option<String>) -> Result<Option<Config>> {
+    if !Config::exists() {
+        if let Some(key) = api_key {
+            // Nothing to set up: defaults plus the key, and nothing written to disk
+            let config = checked_project_config(Config::default())?;
+            return Ok(Some(config.with_api_key_override(key)));
+        }
+        println!("Welcome to Shy! Let's set up your configuration first.");
+        if !run_init()? {
+            return Ok(None);
+        }
+    }
+
+    let base = match Config::load_base() {
+        Ok(config) => config,
+        Err(e) => match e.downcast_ref::<ConfigParseError>() {
+            // Recreating the user's file can't fix a broken system config
+            Some(parse_error) if parse_error.path != Config::system_config_path() => {
+                if !recover_from_parse_error(parse_error)? {
+                    // The config is still unusable; let scripts notice
+                    std::process::exit(1);
+                }
+                Config::load_base()?
             }
+            _ => return Err(e),
+        },
+    };
+    let config = match choose_profile(&base, profile)? {
+        Some(name) => base.with_profile(&name)?,
+        None => base,
+    };
+    // A project's `.shy.toml` wins over both the config file and the profile
+    let mut config = checked_project_config(config)?;
+    match api_key {
+        Some(key) => config = config.with_api_key_override(key),
+        None => config.resolve_api_key()?,
+    }
+    Ok(Some(config))
+}
 
-            let config = Config::load()?;
-            let mut repl = ShyRepl::new(config)?;
-            repl.run().await?;
+/// `config` with the working directory's project file on top, after the user has accepted
+/// its `system_prompt`, and a note on stderr saying what the file changes
+fn checked_project_config(config: Config) -> Result<Config> {
+    let config = confirm_project_prompt(config.with_project_config_from_cwd()?)?;
+    if let Some(project) = &config.project {
+        if !project.values.is_empty() {
+            let keys: Vec<&str> = project.values.keys().map(String::as_str).collect();
+            eprintln!("Using {} (sets {})", project.path.display(), keys.join(", "));
         }
     }
+    Ok(config)
+}
 
-    Ok(())
+/// A project's `system_prompt` replaces shy's instructions, so a cloned repository could
+/// steer the suggestions. Like direnv's `allow`, it is used only once the user has read and
+/// accepted it, and again after every change to it
+fn confirm_project_prompt(config: Config) -> Result<Config> {
+    use dialoguer::Confirm;
+    use std::io::IsTerminal;
+
+    let Some((path, prompt)) = config.project_system_prompt() else {
+        return Ok(config);
+    };
+    let path = path.to_path_buf();
+    let key = config::project_prompt_key(&path, prompt);
+    let list = Config::trusted_prompts_path()?;
+    if config::is_prompt_trusted(&list, &key) {
+        return Ok(config);
+    }
+
+    if !std::io::stdin().is_terminal() || !console::Term::stderr().is_term() {
+        eprintln!(
+            "Warning: not using the system_prompt from {}; run shy in a terminal there once to review it.",
+            path.display()
+        );
+        return Ok(config.without_project_system_prompt());
+    }
+
+    eprintln!("{} sets these instructions for the AI:\n\n{}\n", path.display(), prompt.trim());
+    let accepted = Confirm::new()
+        .with_prompt("Use them whenever you run shy here?")
+        .default(false)
+        .interact_on(&console::Term::stderr())
+        .unwrap_or(false);
+    if !accepted {
+        eprintln!("Using your own instructions instead.");
+        return Ok(config.without_project_system_prompt());
+    }
+    config::trust_prompt(&list, &key, &path)?;
+    Ok(config)
+}
+
+async fn run_repl_or_prompt(repl: &mut ShyRepl, prompt: &[String]) -> Result<()> {
+    if prompt.is_empty() {
+        repl.run().await
+    } else {
+        repl.run_once(&prompt.join(" ")).await
+    }
 }
@@ -0,0 +1,101 @@
+use regex::Regex;
+
+use crate::shell::shell_words;
+
+/// Nouns models use for blanks. An uppercase word is only a blank when it is one of
+/// these (`URL`, `SOURCE_DIR`, `FILE2`), so arguments like `ERROR`, `TERM` or `DROP` stay
+const BLANK_NOUNS: &[&str] = &[
+    "FILE", "FILENAME", "DIR", "DIRECTORY", "FOLDER", "PATH", "URL", "HOST", "HOSTNAME", "PORT",
+    "USER", "USERNAME", "PASSWORD", "EMAIL", "BRANCH", "PID", "IP", "ADDRESS", "DOMAIN",
+    "ARCHIVE", "IMAGE", "CONTAINER", "PACKAGE", "VERSION", "TOKEN", "KEY",
+];
+
+/// Flags whose argument is a file or location, so an uppercase word after them
+/// (`-o OUT`, `--output=REPORT`) is a blank whatever it is called
+const FILE_FLAGS: &[&str] = &["-o", "-O", "-f", "-i", "--output", "--out", "--file", "--input"];
+
+/// Blanks in a suggested command, in order of first appearance: `<file>`-style
+/// placeholders anywhere, and unquoted uppercase words that clearly stand for a value:
+/// named like one (`FILE`, `URL`), following a file flag (`-o OUT`, `--output=OUT`), or
+/// also written as a `<…>` placeholder
+pub fn find_placeholders(command: &str) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    let mut add = |placeholder: String| {
+        if !found.contains(&placeholder) {
+            found.push(placeholder);
+        }
+    };
+
+    let words = shell_words(command);
+    let angles: Vec<&str> = angle_regex().find_iter(command).map(|m| m.as_str()).collect();
+    let mut previous: Option<&str> = None;
+    for word in &words {
+        for angle in angle_regex().find_iter(word) {
+            add(angle.as_str().to_string());
+        }
+        let after_file_flag = previous.is_some_and(|flag| FILE_FLAGS.contains(&flag));
+        previous = Some(word);
+        if word.starts_with(['\'', '"', '$']) {
+            continue;
+        }
+        // `--output=FILE` has its blank after the `=`
+        let (candidate, after_file_flag) = match word.split_once('=') {
+            Some((flag, value)) if flag.starts_with('-') => (value, FILE_FLAGS.contains(&flag)),
+            _ => (word.as_str(), after_file_flag),
+        };
+        if !is_uppercase_word(candidate) {
+            continue;
+        }
+        let also_angle = angles
+            .iter()
+            .any(|angle| angle[1..angle.len() - 1].eq_ignore_ascii_case(candidate));
+        if after_file_flag || also_angle || is_blank_noun(candidate) {
+            add(candidate.to_string());
+        }
+    }
+
+    found
+}
+
+/// Put each value in place of its placeholder. Values are inserted as typed, so
+/// one containing spaces needs quoting by the user
+pub fn fill_placeholders(command: &str, values: &[(String, String)]) -> String {
+    let mut filled = command.to_string();
+    for (placeholder, value) in values {
+        if placeholder.starts_with('<') {
+            filled = filled.replace(placeholder.as_str(), value);
+        } else {
+            // Only whole words, so `FILE` doesn't touch `$FILE` or `FILES`
+            let word = Regex::new(&format!(r"(^|[\s=]){}\b", regex::escape(placeholder)))
+                .expect("escaped placeholder is a valid regex");
+            let replacement = format!("${{1}}{}", value.replace('$', "$$"));
+            filled = word.replace_all(&filled, replacement.as_str()).into_owned();
+        }
+    }
+    filled
+}
+
+fn angle_regex() -> Regex {
+    Regex::new(r"<[A-Za-z][A-Za-z0-9_.\-]*>").expect("valid placeholder regex")
+}
+
+fn is_uppercase_word(word: &str) -> bool {
+    word.len() >= 2
+        && word.starts_with(|c: char| c.is_ascii_uppercase())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// `FILE`, `FILE2`, `SOURCE_DIR`, `YOUR_USERNAME`: some part is a blank noun
+fn is_blank_noun(word: &str) -> bool {
+    word.split('_')
+        .map(|part| part.trim_end_matches(|c: char| c.is_ascii_digit()))
+        .any(|part| BLANK_NOUNS.contains(&part))
+}
+
+/// Whether the command can't run as written: `<…>` blanks are shell redirections, while
+/// an uppercase word left unfilled is still a valid argument
+pub fn needs_values(placeholders: &[String]) -> bool {
+    placeholders.iter().any(|placeholder| placeholder.starts_with('<'))
+}
@@ -0,0 +1,239 @@
+use console::{style, Color};
+use similar::TextDiff;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Result of trying to dry-run a command against temporary copies of its files
+pub enum EditPreview {
+    /// The command does not appear to modify files
+    NotFileModifying,
+    /// Unified diffs for each file the command would change
+    Diff(Vec<(PathBuf, String)>),
+    /// The command modifies files but cannot be safely simulated
+    Unavailable(String),
+}
+
+/// Commands that edit files in place
+const IN_PLACE_EDITORS: &[&str] = &["sed", "gsed", "perl"];
+
+/// In-place editors that are safe to re-run on a copy: sed under `--sandbox`, which
+/// rejects the `e`, `r` and `w` commands. Perl can run arbitrary code, so it never is
+const SIMULATED_EDITORS: &[&str] = &["sed", "gsed"];
+
+/// Commands that write files and cannot be simulated safely
+const FILE_WRITERS: &[&str] = &["tee", "mv", "cp", "truncate", "dd", "rm", "install"];
+
+pub fn preview_command(command: &str) -> EditPreview {
+    let writes_via_redirect = has_output_redirect(command);
+
+    let Some(args) = shlex::split(command) else {
+        return if writes_via_redirect {
+            EditPreview::Unavailable("output redirection cannot be previewed".to_string())
+        } else {
+            EditPreview::NotFileModifying
+        };
+    };
+
+    let Some(program) = args.first() else {
+        return EditPreview::NotFileModifying;
+    };
+
+    // Look past pipes and `&&` too, so chained in-place edits are still flagged
+    let is_in_place_edit = args.iter().enumerate().any(|(i, arg)| {
+        IN_PLACE_EDITORS.contains(&arg.as_str())
+            && args[i + 1..]
+                .iter()
+                .take_while(|a| !["|", "||", "&&", ";"].contains(&a.as_str()))
+                .any(|a| is_in_place_flag(a))
+    });
+
+    if is_in_place_edit {
+        if has_shell_operators(command) || !IN_PLACE_EDITORS.contains(&program.as_str()) {
+            return EditPreview::Unavailable(
+                "in-place edits combined with pipes or chained commands cannot be previewed"
+                    .to_string(),
+            );
+        }
+        if !SIMULATED_EDITORS.contains(&program.as_str()) {
+            return EditPreview::Unavailable(format!(
+                "`{}` can run arbitrary code, so it is not run before you confirm",
+                program
+            ));
+        }
+        return dry_run_in_place_edit(&args);
+    }
+
+    if writes_via_redirect {
+        return EditPreview::Unavailable("output redirection cannot be previewed".to_string());
+    }
+
+    if FILE_WRITERS.contains(&program.as_str()) {
+        return EditPreview::Unavailable(format!("`{}` changes files and cannot be previewed", program));
+    }
+
+    EditPreview::NotFileModifying
+}
+
+fn is_in_place_flag(arg: &str) -> bool {
+    // sed -i, sed -i.bak, sed --in-place, perl -pi, perl -i.bak
+    arg == "--in-place"
+        || arg.starts_with("--in-place=")
+        || (arg.starts_with('-') && !arg.starts_with("--") && arg[1..].contains('i'))
+}
+
+fn has_output_redirect(command: &str) -> bool {
+    let chars: Vec<char> = command.chars().collect();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '>' if !in_single && !in_double => {
+                let mut j = i + 1;
+                if chars.get(j) == Some(&'>') {
+                    j += 1;
+                }
+                // `2>&1` duplicates a descriptor rather than writing a file
+                if chars.get(j) == Some(&'&') {
+                    i = j + 1;
+                    continue;
+                }
+                while chars.get(j) == Some(&' ') {
+                    j += 1;
+                }
+                let target: String = chars[j..]
+                    .iter()
+                    .take_while(|c| !c.is_whitespace())
+                    .collect();
+                if target != "/dev/null" {
+                    return true;
+                }
+                i = j;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    false
+}
+
+fn has_shell_operators(command: &str) -> bool {
+    ["|", ";", "&&", "$(", "`"].iter().any(|op| command.contains(op))
+}
+
+fn dry_run_in_place_edit(args: &[String]) -> EditPreview {
+    let temp_dir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => return EditPreview::Unavailable(format!("could not create temp dir: {}", e)),
+    };
+
+    // Replace every existing file argument with a path to a temporary copy. The
+    // suggestion is untrusted, so sed must not be able to run commands or write files
+    let mut copies = Vec::new();
+    let mut rewritten = vec![args[0].clone(), "--sandbox".to_string()];
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        let path = Path::new(arg);
+        if !arg.starts_with('-') && path.is_file() {
+            let copy = temp_dir.path().join(format!("{}_{}", i, file_name(path)));
+            if let Err(e) = fs::copy(path, &copy) {
+                return EditPreview::Unavailable(format!("could not copy {}: {}", arg, e));
+            }
+            rewritten.push(copy.to_string_lossy().into_owned());
+            copies.push((path.to_path_buf(), copy));
+        } else {
+            rewritten.push(arg.clone());
+        }
+    }
+
+    if copies.is_empty() {
+        return EditPreview::Unavailable("no existing files found in the command".to_string());
+    }
+
+    let output = Command::new(&rewritten[0])
+        .args(&rewritten[1..])
+        .current_dir(temp_dir.path())
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            return EditPreview::Unavailable(format!(
+                "dry run failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+        Err(e) => return EditPreview::Unavailable(format!("dry run failed: {}", e)),
+    }
+
+    let mut diffs = Vec::new();
+    for (original, copy) in copies {
+        let (Ok(before), Ok(after)) = (fs::read_to_string(&original), fs::read_to_string(&copy))
+        else {
+            return EditPreview::Unavailable(format!("{} is not a text file", original.display()));
+        };
+
+        if before != after {
+            let name = original.display().to_string();
+            let diff = TextDiff::from_lines(&before, &after)
+                .unified_diff()
+                .context_radius(2)
+                .header(&name, &name)
+                .to_string();
+            diffs.push((original, diff));
+        }
+    }
+
+    EditPreview::Diff(diffs)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "file".to_string())
+}
+
+/// Print a preview in the same style as the command preview
+pub fn display_preview(preview: &EditPreview) {
+    match preview {
+        EditPreview::NotFileModifying => {}
+        EditPreview::Diff(diffs) if diffs.is_empty() => {
+            println!("{}", style("Preview:").bold().fg(Color::Green));
+            println!("  {}", style("No changes would be made").dim());
+            println!();
+        }
+        EditPreview::Diff(diffs) => {
+            println!("{}", style("Preview:").bold().fg(Color::Green));
+            for (_, diff) in diffs {
+                for line in diff.lines() {
+                    let styled = if line.starts_with("+++") || line.starts_with("---") {
+                        style(line).bold()
+                    } else if line.starts_with('+') {
+                        style(line).fg(Color::Green)
+                    } else if line.starts_with('-') {
+                        style(line).fg(Color::Red)
+                    } else if line.starts_with("@@") {
+                        style(line).fg(Color::Cyan)
+                    } else {
+                        style(line).dim()
+                    };
+                    println!("  {}", styled);
+                }
+            }
+            println!();
+        }
+        EditPreview::Unavailable(reason) => {
+            println!(
+                "{} {}",
+                style("⚠").fg(Color::Yellow),
+                style(format!("Cannot preview file changes: {}", reason)).dim()
+            );
+            println!();
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::path::Path;
+
+/// Marker files and the project type they indicate, in the order they are reported
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "Rust (Cargo)"),
+    ("package.json", "JavaScript/TypeScript (npm)"),
+    ("pyproject.toml", "Python"),
+    ("requirements.txt", "Python"),
+    ("go.mod", "Go (modules)"),
+    ("Gemfile", "Ruby (Bundler)"),
+    ("pom.xml", "Java (Maven)"),
+    ("build.gradle", "Java/Kotlin (Gradle)"),
+    ("CMakeLists.txt", "C/C++ (CMake)"),
+    ("Makefile", "Make"),
+];
+
+/// Project types whose marker files exist directly in `dir`, without duplicates
+pub fn detect_project_types(dir: &Path) -> Vec<&'static str> {
+    let mut types = Vec::new();
+    for (marker, project_type) in PROJECT_MARKERS {
+        if dir.join(marker).is_file() && !types.contains(project_type) {
+            types.push(*project_type);
+        }
+    }
+    types
+}
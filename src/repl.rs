@@ -1,49 +1,123 @@
-use crate::api::OpenRouterClient;
-use crate::config::{Config, AVAILABLE_MODELS};
+use crate::api::{highlight_command, ApiError, ApiErrorKind, OpenRouterClient};
+use crate::cache::AnswerCache;
+use crate::command_log::{self, CommandSource, LogEntry};
+use crate::context_window;
+use crate::config::{
+    model_menu_items, Config, FollowUpRule, MenuDefault, MenuStyle, SuggestionMenu, AVAILABLE_MODELS,
+    SYSTEM_CONFIG_PATH,
+};
+use crate::feedback::{self, Feedback, Rating};
+use crate::jobs::{run_with_timeout, BackgroundJob, JobStatus, OutputStream, TimedOutput};
+use crate::placeholders::{fill_placeholders, find_placeholders, needs_values};
+use crate::preview::{display_preview, preview_command, EditPreview};
+use crate::project::detect_project_types;
+use crate::redact::Redactor;
+use crate::shell::{
+    expand_command, expand_home, fish_incompatibility, runs_in_cmd, shell_command, split_chain, translate_for_fish, Chain,
+};
+use crate::tools::{command_program, install_hint, is_installed, is_runnable, preferred_tools};
+use crate::safety::{dangerous_reason, is_allowlisted, privilege_reason};
+use crate::session::Session;
+use crate::shared_prompt;
+use crate::undo::{plan_undo, Undo};
 use anyhow::Result;
 use console::{style, Color};
 use reedline::{
     ColumnarMenu, Completer, EditCommand, Emacs, KeyCode, KeyModifiers, Prompt, PromptEditMode,
     PromptHistorySearch, Reedline, ReedlineEvent, ReedlineMenu, Signal, Suggestion,
 };
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub struct ShyRepl {
     line_editor: Reedline,
     prompt: ShyPrompt,
     client: OpenRouterClient,
     config: Config,
+    redactor: Redactor,
     last_suggested_commands: Vec<String>,
     history_offset: usize,
-    selected_history_source: Option<usize>,
+    dry_run: bool,
+    quiet: bool,
+    last_user_message: Option<String>,
+    current_shell: String,
+    /// `current_shell` was given with `--shell`
+    shell_from_flag: bool,
+    /// Commands run this session, with how to reverse each one
+    undo_log: Vec<(String, Undo)>,
+    /// What the last command run from the REPL printed, for `/summarize`
+    last_output: Option<CapturedOutput>,
+    session: Session,
+    /// Run commands without asking (`--yes`), except dangerous ones
+    assume_yes: bool,
+    /// History files already reported as containing invalid UTF-8
+    warned_history_files: std::sync::Mutex<std::collections::HashSet<PathBuf>>,
+    /// Directory listing and history from the last context build, see `/refresh`
+    context_cache: std::sync::Mutex<Option<ContextCache>>,
+    /// Commands started with `/run --bg`
+    jobs: Vec<BackgroundJob>,
+    /// The last answer stopped at the length limit, so `/continue` can fetch the rest
+    truncated: bool,
+    /// `--no-cache`: ignore `cache_answers` for this run
+    no_cache: bool,
+    /// The `prompt_url` template, fetched once when the session starts
+    shared_prompt: Option<String>,
+    /// The current model's context window in tokens, when OpenRouter reports it
+    context_limit: Option<usize>,
+    /// Variables from `/env set`, added to the environment of every command run
+    session_env: BTreeMap<String, String>,
+    /// Files from `/attach`, re-read and sent with every message until `/detach`
+    attachments: Vec<PathBuf>,
+}
+
+/// How long a directory scan and parsed history are reused between messages
+const CONTEXT_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// The slow parts of the environment context, kept for quick back-and-forth
+pub(crate) struct ContextCache {
+    pub(crate) dir: PathBuf,
+    pub(crate) scanned_at: std::time::Instant,
+    pub(crate) files: Vec<String>,
+    pub(crate) project_types: Vec<&'static str>,
+    pub(crate) history: Vec<String>,
+}
+
+impl ContextCache {
+    /// Still usable in `dir` at `now`: same directory and within the TTL
+    pub(crate) fn is_fresh(&self, dir: &Path, now: std::time::Instant) -> bool {
+        self.dir == dir && now.saturating_duration_since(self.scanned_at) < CONTEXT_CACHE_TTL
+    }
 }
 
 #[derive(Clone)]
-struct ShyPrompt;
+struct ShyPrompt {
+    indicator: String,
+}
 
 impl Prompt for ShyPrompt {
-    fn render_prompt_left(&self) -> std::borrow::Cow<str> {
+    fn render_prompt_left(&self) -> std::borrow::Cow<'_, str> {
         "".into()
     }
 
-    fn render_prompt_right(&self) -> std::borrow::Cow<str> {
+    fn render_prompt_right(&self) -> std::borrow::Cow<'_, str> {
         "".into()
     }
 
-    fn render_prompt_indicator(&self, _edit_mode: PromptEditMode) -> std::borrow::Cow<str> {
-        "〉".into()
+    fn render_prompt_indicator(&self, _edit_mode: PromptEditMode) -> std::borrow::Cow<'_, str> {
+        self.indicator.as_str().into()
     }
 
-    fn render_prompt_multiline_indicator(&self) -> std::borrow::Cow<str> {
-        "〉".into()
+    fn render_prompt_multiline_indicator(&self) -> std::borrow::Cow<'_, str> {
+        self.indicator.as_str().into()
     }
 
     fn render_prompt_history_search_indicator(
         &self,
         _history_search: PromptHistorySearch,
-    ) -> std::borrow::Cow<str> {
+    ) -> std::borrow::Cow<'_, str> {
         "search: ".into()
     }
 }
@@ -60,8 +134,8 @@ struct CommandInfo {
 }
 
 impl ShyCompleter {
-    fn new() -> Self {
-        let commands = vec![
+    fn new(aliases: &BTreeMap<String, String>) -> Self {
+        let mut commands = vec![
             CommandInfo {
                 name: "/help".to_string(),
                 description: "Show available commands".to_string(),
@@ -70,17 +144,29 @@ impl ShyCompleter {
                 name: "/exit".to_string(),
                 description: "Exit the assistant".to_string(),
             },
+            CommandInfo {
+                name: "/new".to_string(),
+                description: "Start a fresh conversation".to_string(),
+            },
             CommandInfo {
                 name: "/model".to_string(),
                 description: "Change AI model".to_string(),
             },
+            CommandInfo {
+                name: "/compare".to_string(),
+                description: "Compare two models' answers".to_string(),
+            },
+            CommandInfo {
+                name: "/models".to_string(),
+                description: "List and search available models".to_string(),
+            },
             CommandInfo {
                 name: "/config".to_string(),
                 description: "Show current configuration".to_string(),
             },
             CommandInfo {
                 name: "/env".to_string(),
-                description: "Show environment information".to_string(),
+                description: "Show environment information (--export for AI context, set/unset for variables)".to_string(),
             },
             CommandInfo {
                 name: "/run".to_string(),
@@ -90,14 +176,153 @@ impl ShyCompleter {
                 name: "/history".to_string(),
                 description: "Show recent bash history".to_string(),
             },
+            CommandInfo {
+                name: "/retry".to_string(),
+                description: "Ask again for an exact runnable command".to_string(),
+            },
+            CommandInfo {
+                name: "/continue".to_string(),
+                description: "Finish an answer that was cut off".to_string(),
+            },
+            CommandInfo {
+                name: "/nocache".to_string(),
+                description: "Ask again without using a cached answer".to_string(),
+            },
+            CommandInfo {
+                name: "/attach".to_string(),
+                description: "Send a file with every message until /detach".to_string(),
+            },
+            CommandInfo {
+                name: "/detach".to_string(),
+                description: "Stop sending an attached file (all of them without a path)".to_string(),
+            },
+            CommandInfo {
+                name: "/pin".to_string(),
+                description: "Same as /attach".to_string(),
+            },
+            CommandInfo {
+                name: "/unpin".to_string(),
+                description: "Same as /detach".to_string(),
+            },
+            CommandInfo {
+                name: "/pinned".to_string(),
+                description: "List attached files".to_string(),
+            },
+            CommandInfo {
+                name: "/summarize".to_string(),
+                description: "Summarize the output of the last command".to_string(),
+            },
+            CommandInfo {
+                name: "/why".to_string(),
+                description: "Explain a suggested command flag by flag".to_string(),
+            },
+            CommandInfo {
+                name: "/good".to_string(),
+                description: "Rate the last answer as useful".to_string(),
+            },
+            CommandInfo {
+                name: "/bad".to_string(),
+                description: "Rate the last answer as not useful".to_string(),
+            },
+            CommandInfo {
+                name: "/ratings".to_string(),
+                description: "Summarize ratings per model".to_string(),
+            },
+            CommandInfo {
+                name: "/save".to_string(),
+                description: "Save this conversation".to_string(),
+            },
+            CommandInfo {
+                name: "/export".to_string(),
+                description: "Write this conversation to a Markdown file".to_string(),
+            },
+            CommandInfo {
+                name: "/undo".to_string(),
+                description: "Reverse the last executed command".to_string(),
+            },
+            CommandInfo {
+                name: "/diff".to_string(),
+                description: "Preview file changes a command would make".to_string(),
+            },
+            CommandInfo {
+                name: "/jobs".to_string(),
+                description: "List background jobs".to_string(),
+            },
+            CommandInfo {
+                name: "/kill".to_string(),
+                description: "Stop a background job".to_string(),
+            },
+            CommandInfo {
+                name: "/log".to_string(),
+                description: "Show recently executed commands".to_string(),
+            },
+            CommandInfo {
+                name: "/refresh".to_string(),
+                description: "Re-read the directory and shell history".to_string(),
+            },
+            CommandInfo {
+                name: "/alias".to_string(),
+                description: "Save a command as /<name>".to_string(),
+            },
         ];
+        commands.extend(aliases.iter().map(|(name, command)| CommandInfo {
+            name: format!("/{}", name),
+            description: command.clone(),
+        }));
 
         Self { commands }
     }
 }
 
+/// Slash commands whose arguments are shell commands that take file paths
+const PATH_ARGUMENT_COMMANDS: &[&str] = &["/run", "/diff"];
+
 impl Completer for ShyCompleter {
     fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let before_cursor = &line[..pos.min(line.len())];
+        let tokens: Vec<&str> = before_cursor.split_whitespace().collect();
+        let new_word = before_cursor.ends_with(char::is_whitespace);
+        let completing_argument = new_word || tokens.len() > 2;
+        // `/run ca<TAB>`: the program, or a path such as `./build.sh`
+        let completing_program = if new_word {
+            tokens.len() == 1
+        } else {
+            tokens.len() == 2
+        };
+
+        // `/run cat <TAB>`: complete file paths once the program name is typed
+        if !tokens.is_empty()
+            && PATH_ARGUMENT_COMMANDS.contains(&tokens[0])
+            && (completing_program || (tokens.len() >= 2 && completing_argument))
+        {
+            let start = before_cursor
+                .rfind(char::is_whitespace)
+                .map_or(0, |i| i + 1);
+            let partial = &before_cursor[start..];
+            let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+
+            let mut completions = Vec::new();
+            let looks_like_path = partial.contains('/') || partial.starts_with(['.', '~']);
+            if completing_program && !partial.is_empty() && !looks_like_path {
+                let path_var = env::var_os("PATH").unwrap_or_default();
+                completions = program_completions(partial, &path_var);
+            }
+            if completions.is_empty() {
+                completions = path_completions(partial, &cwd);
+            }
+
+            return completions
+                .into_iter()
+                .map(|path| Suggestion {
+                    append_whitespace: !path.ends_with('/'),
+                    value: path,
+                    description: None,
+                    extra: None,
+                    span: reedline::Span::new(start, pos),
+                })
+                .collect();
+        }
+
         if line.starts_with('/') {
             self.commands
                 .iter()
@@ -116,12 +341,745 @@ impl Completer for ShyCompleter {
     }
 }
 
+/// Executables on `path_var` (a `PATH` value) whose names start with `partial`, sorted
+/// and without repeats
+pub(crate) fn program_completions(partial: &str, path_var: &std::ffi::OsStr) -> Vec<String> {
+    let mut programs: Vec<String> = env::split_paths(path_var)
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(partial))
+        .collect();
+    programs.sort();
+    programs.dedup();
+    programs
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// File system completions for a partially typed path, relative to `cwd`.
+/// Directories get a trailing `/`; a leading `~` is expanded for lookup but kept in the result.
+pub(crate) fn path_completions(partial: &str, cwd: &std::path::Path) -> Vec<String> {
+    if partial == "~" {
+        return vec!["~/".to_string()];
+    }
+
+    let (dir_part, file_prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..=i], &partial[i + 1..]),
+        None => ("", partial),
+    };
+
+    let search_dir = if dir_part.is_empty() {
+        cwd.to_path_buf()
+    } else if let Some(rest) = dir_part.strip_prefix("~/") {
+        match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => return Vec::new(),
+        }
+    } else {
+        cwd.join(dir_part)
+    };
+
+    let Ok(entries) = fs::read_dir(&search_dir) else {
+        return Vec::new();
+    };
+
+    let mut completions: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            // Hidden files only when explicitly asked for
+            let hidden = name.starts_with('.') && !file_prefix.starts_with('.');
+            if !name.starts_with(file_prefix) || hidden {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(format!("{}{}{}", dir_part, name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+
+    completions.sort();
+    completions
+}
+
+/// Per-file cap on inlined file contents, in bytes
+const MAX_REFERENCED_FILE_BYTES: usize = 16 * 1024;
+
+/// Total cap on the contents of `/attach`ed files sent with one message, in bytes
+pub(crate) const MAX_ATTACHED_BYTES: usize = 48 * 1024;
+
+/// Largest `/run --capture` value. Captures are passed to every later command in its
+/// environment, and Linux refuses to start a program with a variable over 128 KiB
+pub(crate) const MAX_CAPTURE_BYTES: usize = 64 * 1024;
+
+/// The value `/run --capture` stores for `stdout`, like `$(...)` without trailing newlines
+/// and NUL bytes, or its size when it is over `MAX_CAPTURE_BYTES`
+pub(crate) fn capture_value(stdout: &str) -> std::result::Result<String, usize> {
+    let value = stdout.trim_end_matches(['\n', '\r']).replace('\0', "");
+    if value.len() > MAX_CAPTURE_BYTES {
+        return Err(value.len());
+    }
+    Ok(value)
+}
+
+/// At most this many referenced files are inlined into one message
+const MAX_REFERENCED_FILES: usize = 3;
+
+/// Files inside `cwd` that the message mentions by (relative) path, in order of mention
+pub(crate) fn referenced_files(message: &str, cwd: &std::path::Path) -> Vec<PathBuf> {
+    let Ok(cwd) = cwd.canonicalize() else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for token in message.split_whitespace() {
+        let token = token.trim_matches(|c: char| "'\"`,;:?!()[]{}<>".contains(c));
+        let token = token.strip_suffix('.').unwrap_or(token);
+        if token.is_empty() {
+            continue;
+        }
+
+        let Ok(path) = cwd.join(token).canonicalize() else {
+            continue;
+        };
+        // Never reach outside the working directory (e.g. ../../etc/passwd)
+        if path.is_file() && path.starts_with(&cwd) && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    files.truncate(MAX_REFERENCED_FILES);
+    files
+}
+
+/// A file's contents as sent to the model, between BEGIN/END markers naming it
+fn file_block(name: &str, contents: &str, truncated: bool) -> String {
+    let mut block = format!("----- BEGIN FILE {} -----\n", name);
+    block.push_str(contents);
+    if !contents.ends_with('\n') {
+        block.push('\n');
+    }
+    if truncated {
+        block.push_str(&format!(
+            "[... truncated after {} bytes ...]\n",
+            MAX_REFERENCED_FILE_BYTES
+        ));
+    }
+    block.push_str(&format!("----- END FILE {} -----\n\n", name));
+    block
+}
+
+/// Read a text file for inclusion in the prompt; `None` for binary or unreadable files
+fn read_text_for_context(path: &std::path::Path) -> Option<(String, bool)> {
+    let bytes = fs::read(path).ok()?;
+    let sample = &bytes[..bytes.len().min(8 * 1024)];
+    if sample.contains(&0) {
+        return None;
+    }
+
+    let truncated = bytes.len() > MAX_REFERENCED_FILE_BYTES;
+    let head = &bytes[..bytes.len().min(MAX_REFERENCED_FILE_BYTES)];
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // The cap may cut a multi-byte character in half; drop the partial bytes
+        Err(e) if truncated && e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).ok()?
+        }
+        Err(_) => return None,
+    };
+
+    Some((text.to_string(), truncated))
+}
+
+/// Read a history file, replacing invalid UTF-8 (pasted binary, odd locales) instead
+/// of failing. The flag says whether anything had to be replaced.
+pub(crate) fn read_history_lossy(path: &Path) -> std::io::Result<(String, bool)> {
+    let bytes = fs::read(path)?;
+    match String::from_utf8(bytes) {
+        Ok(contents) => Ok((contents, false)),
+        Err(e) => Ok((String::from_utf8_lossy(e.as_bytes()).into_owned(), true)),
+    }
+}
+
+/// Case-insensitive substring filter over history entries, preserving order
+pub(crate) fn filter_history(commands: Vec<String>, term: &str) -> Vec<String> {
+    let term = term.to_lowercase();
+    commands
+        .into_iter()
+        .filter(|cmd| cmd.to_lowercase().contains(&term))
+        .collect()
+}
+
+/// The editor for composing long prompts: `$VISUAL`/`$EDITOR` (which may carry
+/// arguments, e.g. `code --wait`), else a platform default
+pub(crate) fn buffer_editor_command(editor: Option<String>) -> std::process::Command {
+    let fallback = if cfg!(target_os = "windows") { "notepad" } else { "vi" };
+    command_from_env(editor, &[fallback])
+}
+
+/// `$PAGER` (which may carry arguments), else `less -R` so colors survive
+pub(crate) fn pager_command(pager: Option<String>) -> std::process::Command {
+    let fallback: &[&str] = if cfg!(target_os = "windows") { &["more"] } else { &["less", "-R"] };
+    command_from_env(pager, fallback)
+}
+
+/// Build a command from an environment variable's words, or `fallback` when unset
+fn command_from_env(value: Option<String>, fallback: &[&str]) -> std::process::Command {
+    let words = value
+        .as_deref()
+        .and_then(shlex::split)
+        .filter(|words| !words.is_empty())
+        .unwrap_or_else(|| fallback.iter().map(|w| w.to_string()).collect());
+
+    let mut command = std::process::Command::new(&words[0]);
+    command.args(&words[1..]);
+    command
+}
+
+/// Await `future`, also returning how many seconds it took
+async fn timed<T>(future: impl std::future::Future<Output = T>) -> (T, f32) {
+    let started = std::time::Instant::now();
+    let output = future.await;
+    (output, started.elapsed().as_secs_f32())
+}
+
+fn is_model_not_found(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<ApiError>()
+        .is_some_and(|e| e.kind == ApiErrorKind::ModelNotFound)
+}
+
+/// The shell a process name or path such as `-zsh` or `/usr/local/bin/fish` refers to
+pub(crate) fn shell_from_process_name(name: &str) -> Option<&'static str> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name).to_lowercase();
+    if name.contains("fish") {
+        Some("fish")
+    } else if name.contains("zsh") {
+        Some("zsh")
+    } else if name.contains("bash") {
+        Some("bash")
+    } else if name.contains("pwsh") || name.contains("powershell") {
+        Some("powershell")
+    } else {
+        None
+    }
+}
+
+/// PowerShell exports PSModulePath; on Windows cmd.exe inherits the system-wide
+/// value, but PowerShell prepends the user's module directories to it. Elsewhere the
+/// variable outlives PowerShell (bash started from pwsh, containers that export it), so
+/// it only counts when `SHELL` is unset too
+pub(crate) fn powershell_from_env(
+    module_path: Option<&str>,
+    shell_var: Option<&str>,
+    windows: bool,
+) -> Option<&'static str> {
+    let module_path = module_path?;
+    let trusted = if windows {
+        env::split_paths(module_path).count() >= 3
+    } else {
+        shell_var.is_none()
+    };
+    trusted.then_some("powershell")
+}
+
+/// Which of `layers` (highest precedence first) set `key` to `current`: its label,
+/// "default" when none did, or "changed this session" when the files say otherwise
+pub(crate) fn setting_source(
+    key: &str,
+    current: Option<&toml::Value>,
+    layers: &[(String, toml::Table)],
+) -> String {
+    match layers.iter().find(|(_, table)| table.contains_key(key)) {
+        Some((label, table)) if table.get(key) == current => label.clone(),
+        Some(_) => "changed this session".to_string(),
+        None => "default".to_string(),
+    }
+}
+
+/// An API key with only its last 4 characters visible
+pub(crate) fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.trim().chars().collect();
+    match chars.len() {
+        0 => "not set".to_string(),
+        // Too short to show any of it safely
+        1..=11 => "••••".to_string(),
+        n => format!("••••{}", chars[n - 4..].iter().collect::<String>()),
+    }
+}
+
+/// `Model: old → new` after a setting changes: the old value in red, the new one in
+/// bold green, so it is clear which switch happened
+pub(crate) fn setting_change(label: &str, before: &str, after: &str) -> String {
+    format!(
+        "{}: {} {} {}",
+        label,
+        style(before).fg(Color::Red),
+        style("→").dim(),
+        style(after).fg(Color::Green).bold()
+    )
+}
+
+/// `KEY=VALUE` from `/env set`; the value may be quoted, e.g. `GREETING="hello world"`
+pub(crate) fn parse_env_assignment(assignment: &str) -> std::result::Result<(String, String), String> {
+    let words = shlex::split(assignment).unwrap_or_default();
+    let [word] = words.as_slice() else {
+        return Err("Expected one KEY=VALUE (quote values with spaces)".to_string());
+    };
+    let Some((name, value)) = word.split_once('=') else {
+        return Err(format!("No value given for {}", word));
+    };
+    if !valid_variable_name(name) {
+        return Err(format!("{} is not a valid variable name", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Letters, digits and underscores, not starting with a digit
+pub(crate) fn valid_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The session variables among `names` that the message refers to as `$NAME` or `${NAME}`
+pub(crate) fn mentioned_variables<'a>(
+    message: &str,
+    names: impl IntoIterator<Item = &'a String>,
+) -> Vec<&'a str> {
+    let mentions = |name: &str| {
+        message.contains(&format!("${{{}}}", name))
+            || message.match_indices(&format!("${}", name)).any(|(i, found)| {
+                !message[i + found.len()..]
+                    .starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+            })
+    };
+    names
+        .into_iter()
+        .map(String::as_str)
+        .filter(|name| mentions(name))
+        .collect()
+}
+
+/// Commands suggested in an AI response, from numbered lists, fenced blocks and
+/// inline code, in that order and without repeats, keeping at most `max`
+pub fn extract_commands(response: &str, max: usize) -> Vec<String> {
+    use regex::Regex;
+
+    let mut commands = Vec::new();
+
+    // Extract from numbered lists (most common pattern in AI responses)
+    if let Ok(numbered_regex) = Regex::new(r"^\d+\.\s*(?:[^:]+:\s*)?(.+)$") {
+        for line in response.lines() {
+            if let Some(cap) = numbered_regex.captures(line.trim()) {
+                if let Some(command_desc) = cap.get(1) {
+                    let desc = command_desc.as_str().trim();
+                    // Extract the actual command from the description
+                    if let Some(cmd) = ShyRepl::extract_command_from_description(desc) {
+                        commands.push(cmd);
+                    }
+                }
+            }
+        }
+    }
+
+    // Extract from code blocks, without prompts, comments or sample output
+    if let Ok(code_block_regex) =
+        Regex::new(r"```(?:bash|sh|shell|zsh|console)?\n([^`]+)```")
+    {
+        for cap in code_block_regex.captures_iter(response) {
+            if let Some(block) = cap.get(1) {
+                for cmd in code_block_commands(block.as_str()) {
+                    if ShyRepl::looks_like_command(&cmd) {
+                        commands.push(cmd);
+                    }
+                }
+            }
+        }
+    }
+
+    // Extract from inline code - use extended matching for complex commands.
+    // Fences come out first so prose between a block and the next `code` isn't read as code
+    let prose = Regex::new(r"```[^`]*```")
+        .map(|fence| fence.replace_all(response, "\n").into_owned())
+        .unwrap_or_else(|_| response.to_string());
+    if let Ok(inline_code_regex) = Regex::new(r"`([^`]+)`") {
+        for cap in inline_code_regex.captures_iter(&prose) {
+            if let Some(command) = cap.get(1) {
+                let cmd = strip_prompt(command.as_str().trim());
+                // Use extended matching to capture complex commands with pipes
+                if ShyRepl::looks_like_command_extended(cmd) {
+                    commands.push(cmd.to_string());
+                }
+            }
+        }
+    }
+
+    dedupe_commands(commands, max)
+}
+
+/// History files in the order they are tried; the first one that exists is read.
+/// A chosen file that still exists is the only candidate, otherwise the current
+/// shell's own history comes first and every other known file follows
+pub(crate) fn history_search_order(
+    all_paths: Vec<(PathBuf, &'static str)>,
+    chosen: Option<&Path>,
+    current_shell: &str,
+) -> Vec<(PathBuf, &'static str)> {
+    if let Some(chosen) = chosen.filter(|path| path.exists()) {
+        let shell_type = all_paths
+            .iter()
+            .find(|(path, _)| path == chosen)
+            .map_or("Custom", |(_, shell_type)| *shell_type);
+        return vec![(chosen.to_path_buf(), shell_type)];
+    }
+
+    let own_history = match current_shell {
+        "fish" => Some("Fish"),
+        "zsh" => Some("Zsh"),
+        "bash" => Some("Bash"),
+        "powershell" => Some("PowerShell"),
+        _ => None,
+    };
+    let mut paths: Vec<(PathBuf, &'static str)> = all_paths
+        .iter()
+        .find(|(_, shell_type)| Some(*shell_type) == own_history)
+        .cloned()
+        .into_iter()
+        .collect();
+
+    for (path, shell_type) in all_paths {
+        if !paths.iter().any(|(p, _)| p == &path) {
+            paths.push((path, shell_type));
+        }
+    }
+    paths
+}
+
+/// Suggestions from `[[follow_up_rules]]` whose `command` regex matches what ran and
+/// whose `output` regex, if any, matches what it printed
+pub(crate) fn rule_follow_ups(rules: &[FollowUpRule], command: &str, output: &str) -> Vec<String> {
+    let matches = |pattern: &str, text: &str| {
+        regex::Regex::new(pattern).is_ok_and(|re| re.is_match(text))
+    };
+    rules
+        .iter()
+        .filter(|rule| matches(&rule.command, command))
+        .filter(|rule| rule.output.as_deref().is_none_or(|pattern| matches(pattern, output)))
+        .map(|rule| rule.suggest.clone())
+        .collect()
+}
+
+/// Drop repeats and the individual steps of chained commands that were also extracted
+/// whole, so `cd build && make` isn't offered again as `cd build`, then keep `limit`
+pub(crate) fn dedupe_commands(commands: Vec<String>, limit: usize) -> Vec<String> {
+    let chain_steps: Vec<String> = commands
+        .iter()
+        .map(|cmd| split_chain(cmd))
+        .filter(|parts| parts.len() > 1)
+        .flat_map(|parts| parts.into_iter().map(|(part, _)| part))
+        .collect();
+
+    let mut unique: Vec<String> = Vec::new();
+    for command in commands {
+        if !unique.contains(&command) && !chain_steps.contains(&command) {
+            unique.push(command);
+        }
+    }
+    unique.truncate(limit);
+    unique
+}
+
+/// Case-insensitive substring filter over model ids, sorted for display
+pub(crate) fn filter_models(models: Vec<String>, filter: &str) -> Vec<String> {
+    let filter = filter.to_lowercase();
+    let mut matches: Vec<String> = models
+        .into_iter()
+        .filter(|model| model.to_lowercase().contains(&filter))
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Whether `name` can be used for `/alias` without shadowing a built-in command
+pub(crate) fn valid_alias_name(name: &str) -> bool {
+    const BUILT_IN: &[&str] = &[
+        "help", "exit", "new", "model", "models", "compare", "config", "env", "run", "history",
+        "retry", "save", "export", "undo", "diff", "alias", "refresh", "log", "jobs", "kill",
+        "continue", "good", "bad", "ratings", "why", "nocache",
+        "attach", "detach", "summarize", "pin", "unpin", "pinned",
+    ];
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && !BUILT_IN.contains(&name)
+}
+
+/// The command an alias should store: `n` picks the n-th suggestion when there is
+/// one, anything else is taken as the command itself
+pub(crate) fn alias_target(target: &str, suggestions: &[String]) -> String {
+    target
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| suggestions.get(i))
+        .cloned()
+        .unwrap_or_else(|| target.to_string())
+}
+
+/// What to do after an answer with suggestions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MenuChoice {
+    Skip,
+    /// Run the suggestion at this index
+    Run(usize),
+    Custom,
+}
+
+/// Position of `ui.menu_default` in a suggestion menu laid out as "Do nothing", the
+/// `count` suggestions, then "Enter custom command"
+pub(crate) fn menu_default_index(default: MenuDefault, count: usize) -> usize {
+    match default {
+        MenuDefault::First if count > 0 => 1,
+        MenuDefault::Custom => count + 1,
+        _ => 0,
+    }
+}
+
+/// The choice a key stands for in the `menu_style = "keys"` picker, `None` for keys that
+/// mean nothing there. Numbers match the suggestions' numbering in the answer
+pub(crate) fn key_menu_choice(key: &console::Key, count: usize) -> Option<MenuChoice> {
+    use console::Key;
+    match key {
+        Key::Escape | Key::Enter | Key::Char('0') => Some(MenuChoice::Skip),
+        Key::Char('c') | Key::Char('C') => Some(MenuChoice::Custom),
+        Key::Char(digit) => match digit.to_digit(10) {
+            Some(n) if (1..=count as u32).contains(&n) => Some(MenuChoice::Run(n as usize - 1)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The 1-based `/history` number in `!!` (the most recent command) or `!N`
+pub(crate) fn history_reference(arg: &str) -> Option<usize> {
+    match arg {
+        "!!" => Some(1),
+        _ => arg.strip_prefix('!')?.parse().ok().filter(|&n| n > 0),
+    }
+}
+
+/// Shorten menu items to one terminal line each. dialoguer clears a menu by counting
+/// its items, so items that wrap after the window shrinks leave garbage behind
+pub(crate) fn fit_menu_items(items: &[String], width: usize) -> Vec<String> {
+    // Room for dialoguer's `❯ ` marker
+    let available = width.saturating_sub(4).max(10);
+    items
+        .iter()
+        .map(|item| console::truncate_str(item, available, "…").into_owned())
+        .collect()
+}
+
+/// A `Select` laid out for the terminal size right now rather than at startup.
+/// Esc and Ctrl-C pick the first item, which is always the way out (Do nothing,
+/// Cancel, Exit history)
+fn select_for_terminal(prompt: &str, items: &[String], default: usize) -> Result<usize> {
+    use dialoguer::{theme::ColorfulTheme, Select};
+
+    let (rows, columns) = console::Term::stdout().size();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(default)
+        .items(&fit_menu_items(items, columns as usize))
+        // Page long menus instead of letting them scroll off the top
+        .max_length((rows as usize).saturating_sub(2).max(3))
+        .interact_opt();
+    Ok(cancellable(selection)?.flatten().unwrap_or(0))
+}
+
+/// console answers Ctrl-C in a prompt by raising SIGINT, which would end the process.
+/// With tokio's handler registered the signal is only recorded and the prompt returns
+/// its "interrupted" error to `cancellable`. Registration lasts for the life of the
+/// process, so the returned stream can be dropped straight away
+fn survive_prompt_interrupts() {
+    #[cfg(unix)]
+    if let Err(e) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt()) {
+        log::warn!("Could not install the Ctrl-C handler: {}", e);
+    }
+}
+
+/// Esc and Ctrl-C in a prompt mean "never mind", not an error: `None` instead of the
+/// "read interrupted" error dialoguer reports for Ctrl-C
+pub(crate) fn cancellable<T>(result: dialoguer::Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(dialoguer::Error::IO(e)) if e.kind() == std::io::ErrorKind::Interrupted => {
+            // The prompt was abandoned mid-draw with the cursor hidden
+            console::Term::stderr().show_cursor().ok();
+            console::Term::stdout().show_cursor().ok();
+            println!();
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Shell prompts models copy from terminal transcripts
+const PROMPT_PREFIXES: &[&str] = &["$ ", "> ", "% "];
+
+fn strip_prompt(line: &str) -> &str {
+    PROMPT_PREFIXES
+        .iter()
+        .find_map(|prompt| line.strip_prefix(prompt))
+        .map_or(line, str::trim_start)
+}
+
+/// System prompt for `/why`: explain one command instead of suggesting new ones
+const WHY_INSTRUCTIONS: &str = "Instructions: You are a careful shell expert explaining a command \
+before the user runs it. Do not suggest alternative commands or rewrite it.\n\
+Response format:\n\
+- One sentence on what the command does as a whole\n\
+- A bullet for each program, flag, argument, pipe and redirection, in order, saying what it does\n\
+- A final \"Risks\" section: data it can delete or overwrite, privileges it needs, network access, \
+and anything surprising; say \"None\" when it only reads\n";
+
+/// The question `/why` sends about a suggested command
+pub(crate) fn why_message(command: &str) -> String {
+    format!(
+        "Explain this command flag by flag, then list its risks. Do not run or change it:\n\n```\n{}\n```",
+        command
+    )
+}
+
+const SUMMARIZE_INSTRUCTIONS: &str = "Instructions: You summarize the output of a shell command \
+for the user who ran it.\n\
+Response format:\n\
+- One sentence on what the output shows overall\n\
+- Bullets for the key points, and for every error or warning with the line that shows it\n\
+- If something failed, one line on the likely cause\n\
+Keep it short and do not repeat the output.\n";
+
+/// `/summarize` keeps this many characters from the end of the output, where errors
+/// and the latest log lines usually are
+pub(crate) const MAX_SUMMARY_CHARS: usize = 16_000;
+
+/// Output of a command run from the REPL, stdout and stderr interleaved
+#[derive(Debug, Clone)]
+pub(crate) struct CapturedOutput {
+    pub command: String,
+    /// Standard output and error, interleaved as printed
+    pub output: String,
+    /// Standard output alone, for `/run --capture`
+    pub stdout: String,
+    pub exit_code: Option<i32>,
+}
+
+/// The question `/summarize` sends about captured output, cut to its last
+/// `MAX_SUMMARY_CHARS` characters with a note when longer
+pub(crate) fn summarize_message(captured: &CapturedOutput) -> String {
+    let total = captured.output.chars().count();
+    let output = if total > MAX_SUMMARY_CHARS {
+        let tail: String = captured.output.chars().skip(total - MAX_SUMMARY_CHARS).collect();
+        format!(
+            "[... first {} of {} characters omitted ...]\n{}",
+            total - MAX_SUMMARY_CHARS,
+            total,
+            tail
+        )
+    } else {
+        captured.output.clone()
+    };
+    let status = match captured.exit_code {
+        Some(0) => "succeeded".to_string(),
+        Some(code) => format!("exited with code {}", code),
+        None => "was stopped before it finished".to_string(),
+    };
+    format!(
+        "Summarize the output of `{}`, which {}:\n\n```\n{}\n```",
+        captured.command,
+        status,
+        output.trim_end()
+    )
+}
+
+/// Runnable commands in a fenced code block. Comment lines are dropped. A block
+/// written as a transcript (`$ ls` followed by its output) yields each prompted
+/// line on its own; any other block is one command, possibly spanning lines
+pub(crate) fn code_block_commands(block: &str) -> Vec<String> {
+    let lines: Vec<&str> = block
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let is_transcript = lines
+        .iter()
+        .any(|line| PROMPT_PREFIXES.iter().any(|prompt| line.starts_with(prompt)));
+    if is_transcript {
+        return lines
+            .iter()
+            .filter(|line| PROMPT_PREFIXES.iter().any(|prompt| line.starts_with(prompt)))
+            .map(|line| strip_prompt(line))
+            .filter(|command| !command.is_empty() && !command.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+    }
+
+    if lines.is_empty() {
+        Vec::new()
+    } else {
+        vec![lines.join("\n")]
+    }
+}
+
+/// The suggestion `shy exec` runs: the first one that can actually run here
+pub(crate) fn best_command(commands: &[String]) -> Option<String> {
+    commands.iter().find(|command| is_runnable(command)).cloned()
+}
+
+/// Follow-up asking the model to finish an answer that hit the length limit
+pub(crate) fn continue_message(question: &str, answer_so_far: &str) -> String {
+    format!(
+        "Your previous answer was cut off by the length limit. Continue exactly where it \
+         stopped, without repeating what you already wrote.\n\n\
+         Original request:\n{}\n\nYour answer so far:\n{}",
+        question, answer_so_far
+    )
+}
+
+/// Chat message asking the AI to explain or improve a command from history
+pub(crate) fn history_question(command: &str, improve: bool) -> String {
+    if improve {
+        format!(
+            "Suggest an improved version of this command from my shell history and explain what changes: `{}`",
+            command
+        )
+    } else {
+        format!(
+            "Explain what this command from my shell history does: `{}`",
+            command
+        )
+    }
+}
+
 impl ShyRepl {
     pub fn new(config: Config) -> Result<Self> {
         let mut line_editor = Reedline::create();
 
         // Set up completer with instant menu display
-        let completer = ShyCompleter::new();
+        let completer = ShyCompleter::new(&config.aliases);
         let completion_menu = Box::new(
             ColumnarMenu::default()
                 .with_name("completion_menu")
@@ -155,6 +1113,15 @@ impl ShyRepl {
             ]),
         );
 
+        // Ctrl-X opens the buffer in $VISUAL/$EDITOR and submits what comes back, like
+        // bash's Ctrl-X Ctrl-E (reedline has no two-key chords). Ctrl-O opens the
+        // editor without submitting.
+        keybindings.add_binding(
+            KeyModifiers::CONTROL,
+            KeyCode::Char('x'),
+            ReedlineEvent::Multiple(vec![ReedlineEvent::OpenEditor, ReedlineEvent::Submit]),
+        );
+
         // Let reedline handle Enter naturally:
         // - In completion menu: selects completion + submits
         // - My input handler will execute the completed command
@@ -164,46 +1131,186 @@ impl ShyRepl {
             .with_menu(ReedlineMenu::EngineCompleter(completion_menu))
             .with_edit_mode(Box::new(Emacs::new(keybindings)))
             .with_quick_completions(true)
-            .with_partial_completions(true);
+            .with_partial_completions(true)
+            .with_buffer_editor(
+                buffer_editor_command(env::var("VISUAL").ok().or_else(|| env::var("EDITOR").ok())),
+                env::temp_dir().join(format!("shy-prompt-{}.md", std::process::id())),
+            );
 
-        let prompt = ShyPrompt;
-        let client = OpenRouterClient::new(config.api_key.clone(), config.default_model.clone());
+        let prompt = ShyPrompt {
+            indicator: config.ui.prompt_indicator.clone(),
+        };
+        let client = Self::build_client(&config);
+        let redactor = Redactor::new(&config.redact_patterns)?;
+        let current_shell = config
+            .shell
+            .clone()
+            .unwrap_or_else(|| Self::shell_type(&config));
+        let session = Session::new(&config.default_model);
 
         Ok(Self {
             line_editor,
             prompt,
             client,
             config,
+            redactor,
             last_suggested_commands: Vec::new(),
             history_offset: 0,
-            selected_history_source: None,
+            dry_run: false,
+            quiet: false,
+            last_user_message: None,
+            current_shell,
+            shell_from_flag: false,
+            undo_log: Vec::new(),
+            last_output: None,
+            session,
+            assume_yes: false,
+            warned_history_files: Default::default(),
+            context_cache: Default::default(),
+            jobs: Vec::new(),
+            truncated: false,
+            no_cache: false,
+            shared_prompt: None,
+            context_limit: None,
+            session_env: BTreeMap::new(),
+            attachments: Vec::new(),
         })
     }
 
-    pub async fn run(&mut self) -> Result<()> {
-        println!(
-            "{} {}",
-            style("Shy - SHell AI Assistant").bold().fg(Color::Cyan),
-            style(format!("(using {})", self.config.default_model)).fg(Color::Yellow)
-        );
-        println!(
-            "{}",
-            style("Don't be shy, just ask your shell. Type /help for commands").dim()
-        );
-        println!();
+    /// Print the request that would be sent instead of calling the API
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
 
-        loop {
-            let sig = self.line_editor.read_line(&self.prompt)?;
+    /// Suppress the spinner, timing and decorative headers (for scripts and pipes)
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self.rebuild_client();
+        self
+    }
 
-            match sig {
-                Signal::Success(buffer) => {
-                    let input = buffer.trim();
+    /// Always ask the API, even with `cache_answers` on (`--no-cache`)
+    pub fn with_no_cache(mut self, no_cache: bool) -> Self {
+        self.no_cache = no_cache;
+        self.rebuild_client();
+        self
+    }
 
-                    if input.is_empty() {
-                        continue;
-                    }
+    /// Skip execution confirmations; dangerous commands still ask
+    pub fn with_assume_yes(mut self, assume_yes: bool) -> Self {
+        self.assume_yes = assume_yes;
+        self
+    }
 
-                    // All commands starting with / should be executed immediately
+    /// Run commands (and target suggestions) in this shell instead of the configured one
+    pub fn with_shell(mut self, shell: Option<String>) -> Self {
+        if let Some(shell) = shell {
+            self.current_shell = shell;
+            self.shell_from_flag = true;
+        }
+        self
+    }
+
+    /// Answer a single question without entering the interactive loop
+    pub async fn run_once(&mut self, message: &str) -> Result<()> {
+        self.start_session().await;
+        self.handle_chat(message).await
+    }
+
+    /// `shy exec`: ask for a single command, show the answer, then confirm and run the
+    /// suggestion. Nothing runs when the answer has no runnable command, or under `--dry-run`
+    pub async fn exec(&mut self, request: &str) -> Result<()> {
+        survive_prompt_interrupts();
+        self.start_session().await;
+        self.config.max_suggestions = 1;
+        let context = self.create_context(request);
+        let system_prompt = self.system_prompt();
+        if self.dry_run {
+            return self.print_dry_run(&system_prompt, &context);
+        }
+        let response = self
+            .client
+            .stream_chat_with_timing(Some(&system_prompt), &context, std::time::Instant::now(), request)
+            .await?;
+        if response.trim().is_empty() {
+            self.report_empty_response();
+            return Ok(());
+        }
+
+        self.extract_and_store_commands(&response);
+        let Some(command) = best_command(&self.last_suggested_commands) else {
+            eprintln!(
+                "{}",
+                style("No runnable command in the answer; nothing was run.").for_stderr().dim()
+            );
+            return Ok(());
+        };
+
+        self.execute_command_with_confirmation(&command, true, CommandSource::Suggestion)
+            .await
+    }
+
+    /// Whether `shy exec` may run `command` without confirming, under `auto_run_safe`
+    pub(crate) fn exec_runs_unasked(&self, command: &str) -> bool {
+        self.config.auto_run_safe && is_allowlisted(command, &self.config.safe_commands)
+    }
+
+    fn build_client(config: &Config) -> OpenRouterClient {
+        // `/compare` builds its own clients without fallbacks, so each side is the model asked for
+        Self::build_client_for_model(config, &config.default_model)
+            .with_fallback_models(config.fallback_models.clone())
+            .with_cache(AnswerCache::from_config(config))
+    }
+
+    /// A client for the current model and settings, keeping `--quiet` and `--no-cache`
+    fn rebuild_client(&mut self) {
+        let mut client = Self::build_client(&self.config).with_quiet(self.quiet);
+        if self.no_cache {
+            client = client.with_cache(None);
+        }
+        self.client = client;
+    }
+
+    fn build_client_for_model(config: &Config, model: &str) -> OpenRouterClient {
+        OpenRouterClient::new(config.api_key.clone(), model.to_string())
+            .with_timeout(std::time::Duration::from_secs(config.request_timeout_secs))
+            .with_markdown(config.markdown)
+            .with_max_concurrent_requests(config.max_concurrent_requests)
+            .with_proxy(config.proxy.as_deref(), config.danger_accept_invalid_certs)
+            .with_user_agent(config.user_agent.as_deref())
+            .with_stop(config.stop.clone())
+            .with_structured_output(config.structured_output)
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        survive_prompt_interrupts();
+        self.start_session().await;
+        if !self.quiet && self.config.ui.show_banner {
+            println!(
+                "{} {}",
+                style("Shy - SHell AI Assistant").bold().fg(Color::Cyan),
+                style(format!("(using {})", self.config.default_model)).fg(Color::Yellow)
+            );
+            println!(
+                "{}",
+                style("Don't be shy, just ask your shell. Type /help for commands").dim()
+            );
+            println!();
+        }
+
+        loop {
+            let sig = self.line_editor.read_line(&self.prompt)?;
+
+            match sig {
+                Signal::Success(buffer) => {
+                    let input = buffer.trim();
+
+                    if input.is_empty() {
+                        continue;
+                    }
+
+                    // All commands starting with / should be executed immediately
                     // since they're either typed manually or selected from completion
                     if let Err(e) = self.handle_input(input).await {
                         eprintln!(
@@ -214,6 +1321,7 @@ impl ShyRepl {
                     }
                 }
                 Signal::CtrlD | Signal::CtrlC => {
+                    self.offer_save_before_exit()?;
                     println!("{} Goodbye!", style("👋").fg(Color::Cyan));
                     break;
                 }
@@ -240,30 +1348,146 @@ impl ShyRepl {
                 self.show_help();
             }
             "/exit" => {
+                self.offer_save_before_exit()?;
                 println!("{} Goodbye!", style("👋").fg(Color::Cyan));
                 std::process::exit(0);
             }
+            "/new" => self.start_new_conversation()?,
             "/model" => {
                 self.change_model().await?;
             }
-            "/config" => {
-                println!();
-                println!("{}", style("Current Configuration").bold().fg(Color::Cyan));
-                println!(
-                    "  {}: {}",
-                    style("Model").fg(Color::Green),
-                    style(&self.config.default_model).fg(Color::White)
-                );
-                println!(
-                    "  {}: {}",
-                    style("Config file").fg(Color::Green),
-                    style(format!("{:?}", Config::config_path()?)).dim()
-                );
-                println!();
+            "/compare" => match (parts.get(1), parts.get(2)) {
+                (Some(first), Some(second)) => {
+                    let question = parts[3..].join(" ");
+                    self.compare_models(first, second, &question).await?;
+                }
+                _ => {
+                    println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                    println!(
+                        "  {} {}",
+                        style("/compare").fg(Color::Green),
+                        style("<model1> <model2> [question]").dim()
+                    );
+                    println!(
+                        "  {}",
+                        style("Without a question, the last one is asked again.").dim()
+                    );
+                }
+            },
+            "/models" => {
+                self.list_models(parts.get(1).copied()).await;
             }
-            "/env" => {
-                self.show_environment();
+            "/config" => self.show_config()?,
+            "/env" => match parts.get(1) {
+                Some(&"--export") => self.export_context(),
+                Some(&"set") => {
+                    let assignment = command[cmd.len()..].trim()["set".len()..].trim();
+                    match parse_env_assignment(assignment) {
+                        Ok((name, value)) => {
+                            println!(
+                                "{} {}={} for commands run from now on",
+                                style("✓").fg(Color::Green),
+                                style(&name).bold(),
+                                value
+                            );
+                            self.set_session_variable(name, value);
+                        }
+                        Err(message) => {
+                            println!("{} {}", style("✗").fg(Color::Red), message);
+                            println!(
+                                "  {} {}",
+                                style("/env set").fg(Color::Green),
+                                style("KEY=VALUE").dim()
+                            );
+                        }
+                    }
+                }
+                Some(&"unset") => match parts.get(2) {
+                    Some(name) if self.session_env.remove(*name).is_some() => {
+                        println!("{} {} unset", style("✓").fg(Color::Green), style(name).bold());
+                    }
+                    Some(name) => println!(
+                        "{} {} was not set with /env set",
+                        style("•").fg(Color::Cyan),
+                        name
+                    ),
+                    None => {
+                        println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                        println!(
+                            "  {} {}",
+                            style("/env unset").fg(Color::Green),
+                            style("KEY").dim()
+                        );
+                    }
+                },
+                _ => self.show_environment(),
+            },
+            "/run" if parts.get(1) == Some(&"--timeout") => {
+                let rest = command[cmd.len()..].trim()["--timeout".len()..].trim();
+                // Keep the command's own spacing and quoting, as `--bg` does
+                match rest.split_once(char::is_whitespace) {
+                    Some((secs, command)) => {
+                        self.run_with_timeout(secs, command.trim())?;
+                    }
+                    None => {
+                        println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                        println!(
+                            "  {} {}",
+                            style("/run --timeout").fg(Color::Green),
+                            style("<secs> <command>   (0 = no limit)").dim()
+                        );
+                    }
+                }
+            }
+            "/run" if parts.get(1) == Some(&"--capture") => {
+                let rest = command[cmd.len()..].trim()["--capture".len()..].trim();
+                match rest.split_once(char::is_whitespace) {
+                    Some((name, command)) if valid_variable_name(name) => {
+                        self.run_and_capture(name, command.trim()).await?;
+                    }
+                    Some((name, _)) => println!(
+                        "{} {} is not a valid variable name",
+                        style("✗").fg(Color::Red),
+                        name
+                    ),
+                    None => {
+                        println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                        println!(
+                            "  {} {}",
+                            style("/run --capture").fg(Color::Green),
+                            style("<NAME> <command>   (then use $NAME)").dim()
+                        );
+                    }
+                }
+            }
+            "/run" if parts.get(1) == Some(&"--bg") => {
+                let command = command[cmd.len()..].trim()["--bg".len()..].trim();
+                if command.is_empty() {
+                    println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                    println!(
+                        "  {} {}",
+                        style("/run --bg").fg(Color::Green),
+                        style("<command>").dim()
+                    );
+                } else {
+                    self.run_in_background(command)?;
+                }
+            }
+            "/run" if parts.len() == 2 && history_reference(parts[1]).is_some() => {
+                self.run_from_history(parts[1]).await?;
             }
+            "/jobs" => self.list_jobs(),
+            "/kill" => match parts.get(1) {
+                Some(pid) => self.kill_job(pid)?,
+                None => {
+                    println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                    println!(
+                        "  {} {}",
+                        style("/kill").fg(Color::Green),
+                        style("<pid>   (see /jobs)").dim()
+                    );
+                }
+            },
             "/run" => {
                 if parts.len() > 1 {
                     // Direct command execution
@@ -280,7 +1504,7 @@ impl ShyRepl {
                                 .fg(Color::Cyan)
                         );
                         self.display_interactive_commands();
-                        // Note: menu will be shown after chat response, not here
+                        self.prompt_command_selection().await?;
                     } else {
                         println!("{}", style("Usage:").bold().fg(Color::Cyan));
                         println!(
@@ -300,6 +1524,111 @@ impl ShyRepl {
             "/history" => {
                 self.show_bash_history_interactive().await?;
             }
+            "/retry" => {
+                self.retry_last_message().await?;
+            }
+            "/continue" => {
+                self.continue_response().await?;
+            }
+            "/nocache" => {
+                let question = command[cmd.len()..].trim();
+                self.ask_without_cache(question).await?;
+            }
+            "/attach" | "/pin" => {
+                let path = command[cmd.len()..].trim();
+                if path.is_empty() {
+                    self.list_attachments();
+                } else {
+                    self.attach_file(path);
+                }
+            }
+            "/pinned" => self.list_attachments(),
+            "/detach" | "/unpin" => {
+                let path = command[cmd.len()..].trim();
+                self.detach_files((!path.is_empty()).then_some(path));
+            }
+            "/why" => {
+                self.explain_suggestion(parts.get(1).copied()).await?;
+            }
+            "/summarize" => self.summarize_last_output().await?,
+            "/good" => self.rate_last_answer(Rating::Good)?,
+            "/bad" => self.rate_last_answer(Rating::Bad)?,
+            "/ratings" => self.show_ratings()?,
+            "/undo" => {
+                self.undo_last_command().await?;
+            }
+            "/save" => {
+                self.save_session(parts.get(1).map(PathBuf::from))?;
+            }
+            "/export" => match parts[1..] {
+                [path] => self.export_session(Path::new(path), false)?,
+                ["--force", path] => self.export_session(Path::new(path), true)?,
+                _ => {
+                    println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                    println!(
+                        "  {} {}",
+                        style("/export").fg(Color::Green),
+                        style("[--force] <file.md>").dim()
+                    );
+                }
+            },
+            "/diff" => {
+                if parts.len() > 1 {
+                    // Keep the original spacing so quoted sed expressions survive
+                    let command = command[cmd.len()..].trim().to_string();
+                    println!();
+                    println!("  {}", self.format_command_with_syntax(&command));
+                    println!();
+                    match preview_command(&command) {
+                        EditPreview::NotFileModifying => {
+                            println!("{}", style("This command does not modify files.").dim());
+                            println!();
+                        }
+                        preview => display_preview(&preview),
+                    }
+                } else {
+                    println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                    println!(
+                        "  {} {}",
+                        style("/diff").fg(Color::Green),
+                        style("<command>").dim()
+                    );
+                }
+            }
+            "/refresh" => self.refresh_context(),
+            "/log" => {
+                let limit = parts.get(1).and_then(|n| n.parse().ok()).unwrap_or(20);
+                self.show_command_log(limit)?;
+            }
+            "/alias" => match parts.get(1) {
+                None => self.list_aliases(),
+                Some(name) if parts.len() > 2 => {
+                    let target = command[cmd.len()..].trim()[name.len()..].trim();
+                    self.add_alias(name, target)?;
+                }
+                Some(_) => {
+                    println!("{}", style("Usage:").bold().fg(Color::Cyan));
+                    println!(
+                        "  {} {}",
+                        style("/alias").fg(Color::Green),
+                        style("<name> <command>").dim()
+                    );
+                    println!(
+                        "  {} {}",
+                        style("/alias").fg(Color::Green),
+                        style("<name> <n>     (the n-th suggested command)").dim()
+                    );
+                }
+            },
+            _ if self.config.aliases.contains_key(&cmd[1..]) => {
+                let mut alias = self.config.aliases[&cmd[1..]].clone();
+                // Extra words are appended, so `/gs -s` works like a shell alias
+                let extra = command[cmd.len()..].trim();
+                if !extra.is_empty() {
+                    alias = format!("{} {}", alias, extra);
+                }
+                self.execute_command(&alias).await?;
+            }
             _ => {
                 println!(
                     "{} Unknown command: {}. Type {} for available commands.",
@@ -313,6 +1642,196 @@ impl ShyRepl {
         Ok(())
     }
 
+    fn list_aliases(&self) {
+        println!();
+        if self.config.aliases.is_empty() {
+            println!(
+                "{} No aliases yet. Create one with {}",
+                style("ℹ").fg(Color::Blue),
+                style("/alias <name> <command>").fg(Color::Green)
+            );
+        } else {
+            println!("{}", style("Aliases").bold().fg(Color::Cyan));
+            for (name, command) in &self.config.aliases {
+                println!(
+                    "  {}  {}",
+                    style(format!("/{}", name)).fg(Color::Green),
+                    self.format_command_with_syntax(command)
+                );
+            }
+        }
+        println!();
+    }
+
+    fn add_alias(&mut self, name: &str, target: &str) -> Result<()> {
+        let name = name.trim_start_matches('/');
+        if !valid_alias_name(name) {
+            println!(
+                "{} {} can't be used as an alias: use letters, digits, - or _ and avoid built-in command names",
+                style("⚠").fg(Color::Yellow),
+                style(name).fg(Color::Red)
+            );
+            return Ok(());
+        }
+
+        let command = alias_target(target, &self.last_suggested_commands);
+        println!(
+            "{} {} → {}",
+            style("✓").fg(Color::Green),
+            style(format!("/{}", name)).fg(Color::Green),
+            self.format_command_with_syntax(&command)
+        );
+        self.config.aliases.insert(name.to_string(), command);
+        self.config.save()?;
+        Ok(())
+    }
+
+    /// `/config`: the settings in effect, each with where it came from
+    fn show_config(&self) -> Result<()> {
+        let source = |key: &str| {
+            let current = toml::Table::try_from(&self.config)
+                .ok()
+                .and_then(|table| table.get(key).cloned());
+            setting_source(key, current.as_ref(), &self.config_layers())
+        };
+        let row = |label: &str, value: &str, source: &str| {
+            println!(
+                "  {}: {} {}",
+                style(label).fg(Color::Green),
+                style(value).fg(Color::White),
+                style(format!("({})", source)).dim()
+            );
+        };
+
+        println!();
+        println!("{}", style("Current Configuration").bold().fg(Color::Cyan));
+        row("Model", &self.config.default_model, &source("default_model"));
+        if !self.config.fallback_models.is_empty() {
+            row(
+                "Fallback models",
+                &self.config.fallback_models.join(", "),
+                &source("fallback_models"),
+            );
+        }
+
+        let api_key_source = if self.config.stored_api_key.is_some() {
+            "--api-key".to_string()
+        } else if self.config.api_key_command.is_some() {
+            "api_key_command".to_string()
+        } else {
+            source("api_key")
+        };
+        row("API key", &mask_key(&self.config.api_key), &api_key_source);
+
+        let shell_source = if self.shell_from_flag {
+            "--shell".to_string()
+        } else if self.config.shell.is_some() {
+            source("shell")
+        } else if self.config.shell_type.is_some() {
+            source("shell_type")
+        } else {
+            "detected".to_string()
+        };
+        row("Shell", &self.current_shell, &shell_source);
+
+        match (&self.config.proxy, crate::api::proxy_env_var()) {
+            (Some(proxy), _) => row("Proxy", proxy, &source("proxy")),
+            (None, Some((name, proxy))) => row("Proxy", &proxy, &format!("{} env", name)),
+            (None, None) => row("Proxy", "none", "default"),
+        }
+        row("User-Agent", self.client.user_agent(), &source("user_agent"));
+
+        let context_window = self
+            .context_limit
+            .map(|limit| format!("{} tokens", limit))
+            .unwrap_or_else(|| "unknown".to_string());
+        println!(
+            "  {}: {}",
+            style("Context window").fg(Color::Green),
+            style(context_window).fg(Color::White)
+        );
+        if self.config.danger_accept_invalid_certs {
+            println!(
+                "  {} TLS certificate verification is disabled",
+                style("⚠").fg(Color::Yellow)
+            );
+        }
+        if self.no_cache && self.config.cache_answers {
+            println!(
+                "  {} Answer cache skipped for this run (--no-cache)",
+                style("•").fg(Color::Cyan)
+            );
+        }
+
+        let yes_no = |enabled: bool| if enabled { "yes" } else { "no" };
+        println!("  {}", style("Privacy").fg(Color::Green));
+        for (label, key, enabled) in [
+            ("Send shell history", "send_history", self.config.send_history),
+            ("Send file names and contents", "send_files", self.config.send_files),
+            ("Redact secrets", "redact_secrets", self.config.redact_secrets),
+        ] {
+            println!(
+                "    {}: {} {}",
+                style(label).fg(Color::Green),
+                style(yes_no(enabled)).fg(Color::White),
+                style(format!("({})", source(key))).dim()
+            );
+        }
+
+        if let Some(project) = &self.config.project {
+            println!(
+                "  {}: {}",
+                style("Project config").fg(Color::Green),
+                style(format!("{:?}", project.path)).dim()
+            );
+        }
+        let system = Config::system_config_path();
+        if system.exists() {
+            println!(
+                "  {}: {}",
+                style("System config").fg(Color::Green),
+                style(format!("{:?}", system)).dim()
+            );
+        }
+        println!(
+            "  {}: {}",
+            style("Config file").fg(Color::Green),
+            style(format!("{:?}", Config::config_path()?)).dim()
+        );
+        if Config::uses_fallback_config_dir() {
+            println!(
+                "  {} No standard config directory on this system; using the fallback above",
+                style("⚠").fg(Color::Yellow)
+            );
+        }
+        println!();
+        Ok(())
+    }
+
+    /// The settings tables behind `self.config`, highest precedence first
+    fn config_layers(&self) -> Vec<(String, toml::Table)> {
+        let read = |path: &Path| -> toml::Table {
+            fs::read_to_string(path)
+                .ok()
+                .and_then(|contents| toml::from_str(&contents).ok())
+                .unwrap_or_default()
+        };
+
+        let mut layers = Vec::new();
+        if let Some(project) = &self.config.project {
+            layers.push((project.path.display().to_string(), project.values.clone()));
+        }
+        if let Some(name) = &self.config.active_profile {
+            let profile = self.config.profiles.get(name).cloned().unwrap_or_default();
+            layers.push((format!("profile {}", name), profile));
+        }
+        if let Ok(path) = Config::config_path() {
+            layers.push(("config.toml".to_string(), read(&path)));
+        }
+        layers.push((SYSTEM_CONFIG_PATH.to_string(), read(&Config::system_config_path())));
+        layers
+    }
+
     fn show_environment(&self) {
         println!();
         println!(
@@ -364,6 +1883,22 @@ impl ShyRepl {
             }
         }
 
+        if !self.session_env.is_empty() {
+            println!(
+                "  {}:",
+                style("Variables from /env set and /run --capture").fg(Color::Green)
+            );
+            for (name, value) in &self.session_env {
+                // Captured output can run to many lines; the first one is enough here
+                let lines = value.lines().count();
+                let shown = match value.lines().next() {
+                    Some(first) if lines > 1 => format!("{} … ({} lines)", first, lines),
+                    _ => value.clone(),
+                };
+                println!("    {} {}={}", style("•").fg(Color::Cyan), name, style(shown).dim());
+            }
+        }
+
         // System info
         println!(
             "  {}: {}",
@@ -378,6 +1913,51 @@ impl ShyRepl {
         println!();
     }
 
+    fn export_context(&self) {
+        println!();
+        println!(
+            "{}",
+            style("Context sent with your next message").bold().fg(Color::Cyan)
+        );
+        if !self.config.send_history {
+            println!(
+                "{}",
+                style("Shell history is excluded (send_history = false)").dim()
+            );
+        }
+        if !self.config.send_files {
+            println!(
+                "{}",
+                style("Directory listing and file contents are excluded (send_files = false)").dim()
+            );
+        }
+        println!();
+        let source = if self.config.system_prompt.is_some() {
+            match &self.config.project {
+                Some(project) if project.values.contains_key("system_prompt") => {
+                    format!("from {:?}", project.path)
+                }
+                _ => "from system_prompt in config".to_string(),
+            }
+        } else if Config::load_custom_prompt().is_some() {
+            format!("from {:?}", Config::prompt_path().unwrap_or_default())
+        } else if let (Some(url), Some(_)) = (&self.config.prompt_url, &self.shared_prompt) {
+            format!("from {}", url)
+        } else {
+            "built-in".to_string()
+        };
+        println!(
+            "{}",
+            style(format!("[system message, {}]", source)).dim()
+        );
+        println!("{}", self.system_prompt());
+        println!();
+        println!("{}", style("[user message]").dim());
+        print!("{}", self.build_context_prefix());
+        println!("{}", style("User request: <your message>").dim());
+        println!();
+    }
+
     fn show_help(&self) {
         println!();
         println!("{}", style("Available Commands").bold().fg(Color::Cyan));
@@ -385,11 +1965,40 @@ impl ShyRepl {
         let commands = [
             ("/help", "Show this help message"),
             ("/exit", "Exit the assistant"),
+            ("/new", "Start a fresh conversation, forgetting the current one"),
             ("/model", "Change AI model"),
+            ("/models [filter]", "List available models, optionally filtered"),
+            ("/compare <m1> <m2> [question]", "Ask two models the same question"),
             ("/config", "Show current configuration"),
             ("/env", "Show environment information"),
+            ("/env --export", "Print the full context sent to the AI"),
+            ("/env set KEY=VALUE", "Set a variable for the commands run after it"),
+            ("/env unset KEY", "Remove a variable set with /env set"),
             ("/run", "Execute a shell command or show suggested commands"),
-            ("/history", "Show recent shell history with navigation"),
+            ("/history", "Browse, search, re-run or ask about shell history"),
+            ("/diff", "Preview file changes a command would make"),
+            ("/retry", "Ask again for an exact runnable command"),
+            ("/continue", "Get the rest of an answer cut off at the length limit"),
+            ("/nocache [question]", "Ask (or re-ask the last question) without a cached answer"),
+            ("/attach <path>", "Send a file's contents with every message; /attach lists them"),
+            ("/detach [path]", "Stop sending one attached file, or all of them"),
+            ("/pin, /unpin, /pinned", "Same as /attach <path>, /detach [path] and /attach"),
+            ("/summarize", "Summarize the key points and errors in the last command's output"),
+            ("/why [n]", "Explain the n-th suggested command and its risks, without running it"),
+            ("/good, /bad", "Rate the last answer (stored locally in feedback.jsonl)"),
+            ("/ratings", "Show how each model was rated"),
+            ("/undo", "Reverse the last command (mkdir, touch, cp, mv)"),
+            ("/alias", "Save a command as /<name>, or list aliases"),
+            ("/run !! | !N", "Run the last command from shell history, or entry N of /history"),
+            ("/run --timeout <secs> <cmd>", "Run a command with its own time limit"),
+            ("/run --bg <cmd>", "Run a command in the background"),
+            ("/run --capture <NAME> <cmd>", "Keep a command's output in $NAME for later commands and questions"),
+            ("/jobs", "List background jobs and their status"),
+            ("/kill <pid>", "Stop a background job"),
+            ("/log [n]", "Show the last n executed commands (default 20)"),
+            ("/refresh", "Re-read the directory and shell history for context"),
+            ("/save [path]", "Save this conversation as JSON"),
+            ("/export [--force] <file.md>", "Write this conversation as Markdown notes"),
         ];
         
         for (cmd, desc) in &commands {
@@ -408,29 +2017,534 @@ impl ShyRepl {
         println!();
     }
 
-    async fn execute_command(&self, command: &str) -> Result<()> {
-        self.execute_command_with_confirmation(command, true).await
+    async fn execute_command(&mut self, command: &str) -> Result<()> {
+        self.execute_command_with_confirmation(command, true, CommandSource::Manual)
+            .await
     }
 
     async fn execute_command_with_confirmation(
-        &self,
+        &mut self,
         command: &str,
         ask_confirmation: bool,
+        source: CommandSource,
     ) -> Result<()> {
-        let final_command = if ask_confirmation {
-            match self.get_confirmed_command(command)? {
-                Some(cmd) => cmd,
-                None => return Ok(()), // User cancelled
-            }
-        } else {
-            command.to_string()
+        if let Some(command) = self.prepare_command(command, ask_confirmation, source)? {
+            self.run_system_command(&command, source, self.command_timeout())?;
+        }
+        Ok(())
+    }
+
+    /// `/run --capture NAME <command>`: run through the usual confirmation and keep the
+    /// standard output in the session variable `NAME`, like `NAME=$(command)`
+    async fn run_and_capture(&mut self, name: &str, command: &str) -> Result<()> {
+        let previous = self.last_output.take();
+        let result = self.execute_command(command).await;
+        let Some(captured) = &self.last_output else {
+            // Declined or failed to start; nothing ran
+            self.last_output = previous;
+            return result;
         };
 
-        self.run_system_command(&final_command)
+        let value = match capture_value(&captured.stdout) {
+            Ok(value) => value,
+            Err(size) => {
+                println!(
+                    "{} {} was not set: the output is {} KB, more than the {} KB a variable can \
+                     hold. Redirect it to a file instead, e.g. {}",
+                    style("✗").fg(Color::Red),
+                    style(name).bold(),
+                    size.div_ceil(1024),
+                    MAX_CAPTURE_BYTES / 1024,
+                    style(format!("{} > {}.txt", command, name.to_lowercase())).fg(Color::Green)
+                );
+                return result;
+            }
+        };
+        println!(
+            "{} {} captured ({} line{}); use {} in commands and questions",
+            style("✓").fg(Color::Green),
+            style(name).bold(),
+            value.lines().count(),
+            if value.lines().count() == 1 { "" } else { "s" },
+            style(format!("${}", name)).fg(Color::Green)
+        );
+        self.session_env.insert(name.to_string(), value);
+        result
     }
 
-    fn get_confirmed_command(&self, initial_command: &str) -> Result<Option<String>> {
-        use dialoguer::{Confirm, Input};
+    /// `/run !!` and `/run !N`: run a command from shell history, numbered as in
+    /// `/history` (most recent first), through the usual confirmation
+    async fn run_from_history(&mut self, reference: &str) -> Result<()> {
+        let Some(n) = history_reference(reference) else {
+            return Ok(());
+        };
+        let (entries, _, total) = self.get_paginated_history(n - 1, 1, None)?;
+        let Some(command) = entries.into_iter().next() else {
+            let message = match total {
+                0 => "No shell history found, so there is nothing to re-run.".to_string(),
+                total => format!(
+                    "History has {} command{}; {} is out of range.",
+                    total,
+                    if total == 1 { "" } else { "s" },
+                    reference
+                ),
+            };
+            println!("{} {}", style("⚠").fg(Color::Yellow), message);
+            return Ok(());
+        };
+
+        self.execute_command(&command).await
+    }
+
+    /// How long a foreground command may run, from `command_timeout_secs`
+    fn command_timeout(&self) -> Option<Duration> {
+        match self.config.command_timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// `/run --timeout <secs> <cmd>`: run one command with its own time limit
+    fn run_with_timeout(&mut self, secs: &str, command: &str) -> Result<()> {
+        let Ok(secs) = secs.parse::<u64>() else {
+            println!(
+                "{} {} is not a number of seconds",
+                style("⚠").fg(Color::Yellow),
+                style(secs).bold()
+            );
+            return Ok(());
+        };
+        let timeout = (secs > 0).then(|| Duration::from_secs(secs));
+        if let Some(command) = self.prepare_command(command, true, CommandSource::Manual)? {
+            self.run_system_command(&command, CommandSource::Manual, timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Everything that happens before a command runs: shell translation, placeholders,
+    /// the danger check and confirmation. `None` means the user cancelled
+    fn prepare_command(
+        &self,
+        command: &str,
+        ask_confirmation: bool,
+        source: CommandSource,
+    ) -> Result<Option<String>> {
+        let command = &self.adapt_to_shell(command);
+        // Typed commands are taken literally; only suggestions have blanks to fill
+        let command = &match source {
+            CommandSource::Suggestion => match self.prompt_for_placeholders(command)? {
+                Some(filled) => filled,
+                None => return Ok(None),
+            },
+            CommandSource::Manual => command.to_string(),
+        };
+
+        // `--yes` skips the prompt, but destructive commands are always confirmed
+        let danger = dangerous_reason(command);
+        if let Some(reason) = danger {
+            println!(
+                "{} {}",
+                style("⚠ Dangerous:").bold().fg(Color::Red),
+                style(format!("this command {}", reason)).fg(Color::Red)
+            );
+        }
+        // Only `shy exec` asks before running a suggestion; menu picks already run unasked
+        let auto_run = source == CommandSource::Suggestion && self.exec_runs_unasked(command);
+        if auto_run && ask_confirmation {
+            println!(
+                "{} {}",
+                style("▸").fg(Color::Green),
+                style("read-only command on the safe list, running without asking").dim()
+            );
+        }
+        let ask_confirmation =
+            (ask_confirmation && !self.assume_yes && !auto_run) || danger.is_some();
+
+        let final_command = if ask_confirmation {
+            match self.get_confirmed_command(command)? {
+                Some(cmd) => cmd,
+                None => return Ok(None), // User cancelled
+            }
+        } else {
+            command.to_string()
+        };
+
+        Ok(Some(self.normalize_command(&final_command)))
+    }
+
+    /// `/run --bg`: start the command and return to the prompt straight away
+    fn run_in_background(&mut self, command: &str) -> Result<()> {
+        let Some(command) = self.prepare_command(command, true, CommandSource::Manual)? else {
+            return Ok(());
+        };
+
+        let job = BackgroundJob::spawn(self.shell_command(&command), &command)?;
+        // Its exit code isn't known yet; /jobs reports it
+        self.log_command(&command, None, CommandSource::Manual);
+        println!(
+            "{} {} {}",
+            style("▸").fg(Color::Green),
+            style(&command).bold(),
+            style(format!("running in the background, PID {}", job.pid)).fg(Color::Yellow)
+        );
+        println!(
+            "  {} {}",
+            style("Output:").dim(),
+            style(job.log_path.display()).dim()
+        );
+        self.jobs.push(job);
+        Ok(())
+    }
+
+    fn list_jobs(&mut self) {
+        println!();
+        if self.jobs.is_empty() {
+            println!(
+                "{} No background jobs. Start one with {}",
+                style("ℹ").fg(Color::Blue),
+                style("/run --bg <command>").fg(Color::Green)
+            );
+            println!();
+            return;
+        }
+
+        println!("{}", style("Background jobs").bold().fg(Color::Cyan));
+        for job in &mut self.jobs {
+            let status = match job.status() {
+                JobStatus::Running => style(format!(
+                    "running {}s",
+                    job.started.elapsed().as_secs()
+                ))
+                .fg(Color::Green),
+                JobStatus::Exited(Some(0)) => style("done".to_string()).fg(Color::Green),
+                JobStatus::Exited(Some(code)) => style(format!("exit {}", code)).fg(Color::Red),
+                JobStatus::Exited(None) => style("killed".to_string()).fg(Color::Red),
+            };
+            println!(
+                "  {} {} {}",
+                style(format!("{:>7}", job.pid)).fg(Color::Yellow),
+                status,
+                style(&job.command).bold()
+            );
+            println!("          {}", style(job.log_path.display()).dim());
+        }
+        println!();
+    }
+
+    fn kill_job(&mut self, pid: &str) -> Result<()> {
+        let Some(index) = pid
+            .parse::<u32>()
+            .ok()
+            .and_then(|pid| self.jobs.iter().position(|job| job.pid == pid))
+        else {
+            println!(
+                "{} {} is not a background job started here. See {}",
+                style("⚠").fg(Color::Yellow),
+                style(pid).fg(Color::Red),
+                style("/jobs").fg(Color::Green)
+            );
+            return Ok(());
+        };
+
+        let mut job = self.jobs.remove(index);
+        job.terminate()?;
+        println!(
+            "{} Stopped {} ({})",
+            style("✓").fg(Color::Green),
+            style(&job.command).bold(),
+            job.pid
+        );
+        Ok(())
+    }
+
+    fn save_session(&mut self, path: Option<PathBuf>) -> Result<()> {
+        if self.session.turns.is_empty() {
+            println!(
+                "{} Nothing to save yet. Ask a question first.",
+                style("⚠").fg(Color::Yellow)
+            );
+            return Ok(());
+        }
+
+        let path = match path {
+            Some(path) => path,
+            None => Session::default_path()?,
+        };
+        self.session.save(&path)?;
+        println!(
+            "{} Session saved to {}",
+            style("✓").fg(Color::Green),
+            style(path.display()).dim()
+        );
+        Ok(())
+    }
+
+    /// `/export`: an existing file is only replaced after a confirmation, or with `--force`
+    fn export_session(&self, path: &Path, force: bool) -> Result<()> {
+        if self.session.turns.is_empty() {
+            println!(
+                "{} Nothing to export yet. Ask a question first.",
+                style("⚠").fg(Color::Yellow)
+            );
+            return Ok(());
+        }
+        if path.exists() && !force {
+            let overwrite = dialoguer::Confirm::new()
+                .with_prompt(format!("{} already exists. Overwrite it?", path.display()))
+                .default(false)
+                .interact();
+            if !cancellable(overwrite)?.unwrap_or(false) {
+                println!("{} Nothing was exported", style("ℹ").fg(Color::Blue));
+                return Ok(());
+            }
+        }
+
+        self.session.export_markdown(path)?;
+        println!(
+            "{} Conversation written to {}",
+            style("✓").fg(Color::Green),
+            style(path.display()).dim()
+        );
+        Ok(())
+    }
+
+    /// Ask whether to keep an unsaved conversation before it is lost
+    fn offer_save_before_exit(&mut self) -> Result<()> {
+        self.offer_save("Save session before exiting?")
+    }
+
+    fn offer_save(&mut self, prompt: &str) -> Result<()> {
+        if !self.config.confirm_unsaved_exit || !self.session.has_unsaved_turns() {
+            return Ok(());
+        }
+
+        let save = dialoguer::Confirm::new()
+            .with_prompt(prompt)
+            .default(false)
+            .interact();
+        if cancellable(save)?.unwrap_or(false) {
+            self.save_session(None)?;
+        }
+        Ok(())
+    }
+
+    /// `/new`: forget the conversation but keep the client, config, jobs and undo log
+    fn start_new_conversation(&mut self) -> Result<()> {
+        self.offer_save("Save this conversation before starting a new one?")?;
+
+        self.session = Session::new(&self.config.default_model);
+        self.last_user_message = None;
+        self.last_suggested_commands.clear();
+        self.truncated = false;
+        // The next question gets context for wherever the user is now
+        *self.context_cache.lock().unwrap_or_else(|e| e.into_inner()) = None;
+
+        println!(
+            "{} New conversation started with {}",
+            style("✓").fg(Color::Green),
+            style(&self.config.default_model).fg(Color::Cyan)
+        );
+        Ok(())
+    }
+
+    async fn undo_last_command(&mut self) -> Result<()> {
+        let Some((command, undo)) = self.undo_log.pop() else {
+            println!(
+                "{} Nothing to undo yet.",
+                style("⚠").fg(Color::Yellow)
+            );
+            return Ok(());
+        };
+
+        match undo {
+            Undo::Command(inverse) => {
+                println!(
+                    "{} {}",
+                    style("Undoing:").bold().fg(Color::Cyan),
+                    self.format_command_with_syntax(&command)
+                );
+                let depth = self.undo_log.len();
+                self.execute_command(&inverse).await?;
+
+                if self.undo_log.len() > depth {
+                    // The inverse itself is not something to undo later
+                    self.undo_log.truncate(depth);
+                } else {
+                    // Cancelled or failed: keep the entry so /undo can be tried again
+                    self.undo_log.push((command, Undo::Command(inverse)));
+                }
+            }
+            Undo::Unsupported(reason) => {
+                println!(
+                    "{} Cannot undo {}: {}",
+                    style("⚠").fg(Color::Yellow),
+                    style(&command).bold(),
+                    reason
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record an executed command in the audit log. Failing to write it never stops
+    /// the session, it is only reported
+    fn log_command(&self, command: &str, exit_code: Option<i32>, source: CommandSource) {
+        if !self.config.log_commands {
+            return;
+        }
+
+        let cwd = env::current_dir().unwrap_or_default();
+        let entry = LogEntry::new(command, &cwd, exit_code, source);
+        let written = command_log::log_path()
+            .and_then(|path| command_log::append(&path, &entry, command_log::MAX_LOG_BYTES));
+        if let Err(e) = written {
+            log::warn!("Could not write the command log: {}", e);
+        }
+    }
+
+    fn show_command_log(&self, limit: usize) -> Result<()> {
+        let path = command_log::log_path()?;
+        let entries = command_log::recent(&path, limit)?;
+
+        println!();
+        if entries.is_empty() {
+            let note = if self.config.log_commands {
+                "No commands logged yet."
+            } else {
+                "Command logging is off (log_commands = false)."
+            };
+            println!("{} {}", style("ℹ").fg(Color::Blue), note);
+            println!();
+            return Ok(());
+        }
+
+        println!("{}", style("Executed commands").bold().fg(Color::Cyan));
+        for entry in &entries {
+            let status = match entry.exit_code {
+                Some(0) => style("✓".to_string()).fg(Color::Green),
+                Some(code) => style(format!("✗ {}", code)).fg(Color::Red),
+                None => style("✗".to_string()).fg(Color::Red),
+            };
+            let timestamp = chrono::DateTime::parse_from_rfc3339(&entry.timestamp)
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|_| entry.timestamp.clone());
+            let source = match entry.source {
+                CommandSource::Suggestion => "suggested",
+                CommandSource::Manual => "manual",
+            };
+            println!(
+                "  {} {} {}  {}",
+                style(timestamp).dim(),
+                status,
+                self.format_command_with_syntax(&entry.command),
+                style(format!("({}, {})", source, entry.cwd)).dim()
+            );
+        }
+        println!("  {}", style(path.display()).dim());
+        println!();
+        Ok(())
+    }
+
+    /// Ask for a value for each `<name>` or `NAME` blank in a suggested command and
+    /// fill them in; commands without blanks come back unchanged. `None` if the user
+    /// backed out with Ctrl-C, or if nobody is there to ask (`--yes`, or input that isn't
+    /// a terminal), since running the command with the blanks in it would be wrong
+    fn prompt_for_placeholders(&self, command: &str) -> Result<Option<String>> {
+        use std::io::IsTerminal;
+
+        let placeholders = find_placeholders(command);
+        if placeholders.is_empty() {
+            return Ok(Some(command.to_string()));
+        }
+        if self.assume_yes || !std::io::stdin().is_terminal() {
+            // Uppercase words may be real arguments, so the command runs as written
+            if !needs_values(&placeholders) {
+                return Ok(Some(command.to_string()));
+            }
+            eprintln!(
+                "{} Not running `{}`: it has blanks to fill in ({}), which needs an interactive terminal",
+                style("✗").fg(Color::Red),
+                command,
+                placeholders.join(", ")
+            );
+            return Ok(None);
+        }
+
+        println!(
+            "{} {}",
+            style("✎").fg(Color::Cyan),
+            style("Fill in the blanks in this command (Enter keeps the word as written):").dim()
+        );
+        let mut values = Vec::new();
+        for placeholder in placeholders {
+            let value = dialoguer::Input::<String>::new()
+                .with_prompt(&placeholder)
+                .allow_empty(true)
+                .interact_text();
+            let Some(value) = cancellable(value)? else {
+                return Ok(None);
+            };
+            if !value.is_empty() {
+                values.push((placeholder, value));
+            }
+        }
+        Ok(Some(fill_placeholders(command, &values)))
+    }
+
+    /// Rewrite bash syntax the current shell can't run, and warn about what can't be
+    /// rewritten. Only fish needs this; POSIX shells take suggestions as they are
+    fn adapt_to_shell(&self, command: &str) -> String {
+        if self.current_shell != "fish" {
+            return command.to_string();
+        }
+
+        if let Some(problem) = fish_incompatibility(command) {
+            println!(
+                "{} {}",
+                style("⚠").fg(Color::Yellow),
+                style(format!("This command {}", problem)).fg(Color::Yellow)
+            );
+        }
+        match translate_for_fish(command) {
+            Some(translated) => {
+                println!(
+                    "{} {}",
+                    style("↻").fg(Color::Cyan),
+                    style("Translated for fish").dim()
+                );
+                translated
+            }
+            None => command.to_string(),
+        }
+    }
+
+    /// What actually runs. POSIX shells and PowerShell expand `~` and `$var` themselves;
+    /// splicing values into the text would re-parse them as shell code, so their command
+    /// is left as written. Only `cmd /C` gets `~` spelled out
+    fn normalize_command(&self, command: &str) -> String {
+        if runs_in_cmd(&self.current_shell) {
+            expand_home(command)
+        } else {
+            command.to_string()
+        }
+    }
+
+    /// The command with `~` and `$VAR` filled in, for the preview only, with the values
+    /// the command will see: `/env set` ones first, then shy's own environment.
+    /// PowerShell expands `~` and `$var` itself, with different rules than POSIX shells
+    pub(crate) fn expand_for_display(&self, command: &str) -> String {
+        if self.current_shell == "powershell" {
+            command.to_string()
+        } else {
+            expand_command(command, |name| {
+                self.session_env.get(name).cloned().or_else(|| env::var(name).ok())
+            })
+        }
+    }
+
+    fn get_confirmed_command(&self, initial_command: &str) -> Result<Option<String>> {
+        use dialoguer::{Confirm, Input};
         
         let mut current_command = initial_command.to_string();
 
@@ -440,7 +2554,11 @@ impl ShyRepl {
             let should_run = Confirm::new()
                 .with_prompt("Do you want to execute this command?")
                 .default(false)
-                .interact()?;
+                .interact();
+            let Some(should_run) = cancellable(should_run)? else {
+                println!("{}", style("Command cancelled.").fg(Color::Yellow));
+                return Ok(None);
+            };
 
             if should_run {
                 return Ok(Some(current_command));
@@ -449,225 +2567,1192 @@ impl ShyRepl {
             let modify = Confirm::new()
                 .with_prompt("Would you like to modify the command?")
                 .default(false)
-                .interact()?;
+                .interact();
+
+            let modified = match cancellable(modify)? {
+                Some(true) => cancellable(
+                    Input::new()
+                        .with_prompt("Enter modified command")
+                        .with_initial_text(&current_command)
+                        .interact_text(),
+                )?,
+                _ => None,
+            };
+            if let Some(modified) = modified {
+                current_command = modified;
+            } else {
+                println!("{}", style("Command cancelled.").fg(Color::Yellow));
+                return Ok(None);
+            }
+        }
+    }
+
+    fn display_command_preview(&self, command: &str) {
+        println!();
+        println!("{}", style("Command Execution").bold().fg(Color::Cyan));
+        println!(
+            "{} {}",
+            style("•").fg(Color::Green),
+            style("Executing shell command as requested").dim()
+        );
+        println!();
+        println!("{}", style("Command:").bold().fg(Color::Green));
+        println!("  {}", self.format_command_with_syntax(command));
+        println!();
+
+        if let Some(reason) = privilege_reason(command) {
+            println!(
+                "{} {}",
+                style("⚠ Elevated:").bold().fg(Color::Yellow),
+                style(format!("this command {}; check it before confirming", reason))
+                    .fg(Color::Yellow)
+            );
+            println!();
+        }
+
+        let parts = split_chain(command);
+        if parts.len() > 1 {
+            println!("{}", style("Runs in sequence:").bold().fg(Color::Green));
+            let mut previous: Option<Chain> = None;
+            for (i, (part, chain)) in parts.iter().enumerate() {
+                let condition = match previous {
+                    None => String::new(),
+                    Some(Chain::And) => format!("only if step {} succeeds: ", i),
+                    Some(Chain::Or) => format!("only if step {} fails: ", i),
+                    Some(Chain::Then) => "then, regardless: ".to_string(),
+                };
+                println!(
+                    "  {} {}{}",
+                    style(format!("{}.", i + 1)).fg(Color::Green),
+                    style(condition).dim(),
+                    self.format_command_with_syntax(part)
+                );
+                previous = *chain;
+            }
+            println!();
+        }
+
+        let expanded = self.expand_for_display(command);
+        if expanded != command {
+            println!("{}", style("Expands to:").bold().fg(Color::Green));
+            println!("  {}", self.format_command_with_syntax(&expanded));
+            println!();
+        }
+        display_preview(&preview_command(&expanded));
+    }
+
+    /// Show output taller than the terminal through `$PAGER`; false means print it
+    fn page_output(&self, output: &str) -> bool {
+        use std::io::Write;
+        use std::process::Stdio;
+
+        let term = console::Term::stdout();
+        if !self.config.use_pager || !term.is_term() {
+            return false;
+        }
+        let height = term.size().0 as usize;
+        if output.lines().count() + 2 <= height {
+            return false;
+        }
+
+        let Ok(mut pager) = pager_command(env::var("PAGER").ok())
+            .stdin(Stdio::piped())
+            .spawn()
+        else {
+            return false;
+        };
+        if let Some(mut stdin) = pager.stdin.take() {
+            // The user quitting the pager early closes the pipe; that's fine
+            let _ = stdin.write_all(output.as_bytes());
+        }
+        pager.wait().is_ok()
+    }
+
+    /// `/env set`: pass `name` to every command run afterwards, over any exported value
+    pub(crate) fn set_session_variable(&mut self, name: String, value: String) {
+        self.session_env.insert(name, value);
+    }
+
+    /// `command` in the user's shell, with the variables from `/env set`
+    pub(crate) fn shell_command(&self, command: &str) -> std::process::Command {
+        let mut process = shell_command(&self.current_shell, command);
+        process.envs(&self.session_env);
+        process
+    }
+
+    fn run_system_command(
+        &mut self,
+        command: &str,
+        source: CommandSource,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        println!(
+            "{} {}",
+            style("▸").fg(Color::Green),
+            style(command).bold()
+        );
+
+        // The inverse depends on what exists before the command runs
+        let undo = match env::current_dir() {
+            _ if self.current_shell == "powershell" => {
+                Undo::Unsupported("undo is not available in PowerShell".to_string())
+            }
+            Ok(cwd) => plan_undo(command, &cwd),
+            Err(e) => Undo::Unsupported(format!("unknown working directory: {}", e)),
+        };
+
+        // Output streams to the terminal as it arrives, unless it is kept back for the pager
+        let streaming = !(self.config.use_pager && console::Term::stdout().is_term());
+        let started = std::time::Instant::now();
+        let result = run_with_timeout(
+            self.shell_command(command),
+            timeout,
+            |stream, chunk| {
+                use std::io::Write;
+                if !streaming {
+                    return;
+                }
+                match stream {
+                    OutputStream::Stdout => {
+                        let mut stdout = std::io::stdout();
+                        stdout.write_all(chunk).ok();
+                        stdout.flush().ok();
+                    }
+                    OutputStream::Stderr => {
+                        std::io::stderr().write_all(chunk).ok();
+                    }
+                }
+            },
+        );
+        let elapsed = started.elapsed().as_secs_f32();
+        // The command may have created or removed files
+        *self.context_cache.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        let exit_code = match &result {
+            Ok(finished) if !finished.timed_out => finished.output.status.code(),
+            _ => None,
+        };
+        self.log_command(command, exit_code, source);
+
+        match result {
+            Ok(TimedOutput {
+                output,
+                combined,
+                timed_out,
+            }) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let combined = String::from_utf8_lossy(&combined);
+                self.last_output = Some(CapturedOutput {
+                    command: command.to_string(),
+                    output: combined.to_string(),
+                    stdout: stdout.to_string(),
+                    exit_code,
+                });
+
+                if streaming {
+                    // Keep the status line off the end of output without a final newline
+                    if !combined.is_empty() && !combined.ends_with('\n') {
+                        println!();
+                    }
+                } else {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    if !stdout.is_empty() && !self.page_output(&stdout) {
+                        println!("{}", stdout);
+                    }
+                    if !stderr.is_empty() {
+                        eprintln!("{}", stderr);
+                    }
+                }
+
+                if timed_out {
+                    println!(
+                        "{} {}",
+                        style(format!("✗ timed out after {:.0}s, stopped", elapsed)).fg(Color::Red),
+                        style("(allow longer with /run --timeout <secs> <cmd>)").dim()
+                    );
+                } else if output.status.success() {
+                    println!(
+                        "{} {}",
+                        style("✓ done").fg(Color::Green),
+                        style(format!("({:.1}s)", elapsed)).fg(Color::Yellow)
+                    );
+                    self.undo_log.push((command.to_string(), undo));
+
+                    // Analyze output for intelligent follow-up suggestions
+                    if let Some(suggestions) =
+                        self.analyze_command_output(command, &stdout, &combined)
+                    {
+                        self.display_follow_up_suggestions(&suggestions);
+                    }
+                } else {
+                    let status = match output.status.code() {
+                        Some(code) => format!("exit {}", code),
+                        None => output.status.to_string(),
+                    };
+                    println!(
+                        "{} {}",
+                        style(format!("✗ {}", status)).fg(Color::Red),
+                        style(format!("({:.1}s)", elapsed)).fg(Color::Yellow)
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!(
+                    "{} Failed to execute command: {}",
+                    style("✗").fg(Color::Red),
+                    style(e).fg(Color::Red)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Next steps after a successful command: the built-in heuristics unless
+    /// `builtin_follow_ups` is off, then the user's `[[follow_up_rules]]`. The built-in
+    /// ones parse JSON, so they only see stdout (curl's progress meter goes to stderr);
+    /// rules match the combined output
+    fn analyze_command_output(
+        &self,
+        command: &str,
+        stdout: &str,
+        combined: &str,
+    ) -> Option<Vec<String>> {
+        let mut suggestions = if self.config.builtin_follow_ups {
+            self.builtin_follow_ups(command, stdout)
+        } else {
+            Vec::new()
+        };
+        for suggestion in rule_follow_ups(&self.config.follow_up_rules, command, combined) {
+            if !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+        }
+
+        if suggestions.is_empty() {
+            None
+        } else {
+            Some(suggestions)
+        }
+    }
+
+    fn builtin_follow_ups(&self, command: &str, output: &str) -> Vec<String> {
+        let mut suggestions = Vec::new();
+        
+        // XKCD API detection
+        if command.contains("xkcd.com") && command.contains("info.0.json") {
+            if let Some(download_cmd) = self.extract_xkcd_download_suggestion(output) {
+                suggestions.push(download_cmd);
+            }
+        }
+        
+        // JSON API responses with downloadable content
+        if self.looks_like_json(output) {
+            if let Some(download_cmd) = self.extract_download_from_json(output) {
+                suggestions.push(download_cmd);
+            }
+        }
+        
+        // File listings that could benefit from filtering/sorting
+        if command.starts_with("ls") && output.lines().count() > 10 {
+            suggestions.push("Filter results with: ls | grep <pattern>".to_string());
+            suggestions.push("Sort by date: ls -lt".to_string());
+        }
+        
+        // Git commands that often have follow-ups
+        if command.starts_with("git status") && output.contains("modified:") {
+            suggestions.push("git diff".to_string());
+            suggestions.push("git add .".to_string());
+        }
+
+        suggestions
+    }
+
+    fn extract_xkcd_download_suggestion(&self, output: &str) -> Option<String> {
+        
+        // Parse JSON to extract img URL and title
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
+            let img_url = json["img"].as_str()?;
+            let title = json["title"].as_str().unwrap_or("comic");
+            let safe_title = json["safe_title"].as_str().unwrap_or(title);
+            
+            // Extract filename from URL
+            if let Some(filename) = img_url.split('/').next_back() {
+                return Some(format!(
+                    "curl -o '{}.{}' '{}'", 
+                    safe_title, 
+                    filename.split('.').next_back().unwrap_or("png"),
+                    img_url
+                ));
+            }
+        }
+        
+        None
+    }
+
+    fn extract_download_from_json(&self, output: &str) -> Option<String> {
+        // Look for common downloadable file patterns in JSON
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
+            // Check for various download URL patterns
+            for key in &["img", "image", "url", "download_url", "file", "src"] {
+                if let Some(url) = json[key].as_str() {
+                    if self.is_downloadable_url(url) {
+                        if let Some(filename) = url.split('/').next_back() {
+                            return Some(format!("curl -o '{}' '{}'", filename, url));
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn is_downloadable_url(&self, url: &str) -> bool {
+        let downloadable_extensions = [
+            ".png", ".jpg", ".jpeg", ".gif", ".pdf", ".zip", 
+            ".mp4", ".mp3", ".doc", ".txt", ".svg"
+        ];
+        
+        downloadable_extensions.iter().any(|ext| url.ends_with(ext))
+    }
+
+    fn looks_like_json(&self, text: &str) -> bool {
+        let trimmed = text.trim();
+        (trimmed.starts_with('{') && trimmed.ends_with('}')) ||
+        (trimmed.starts_with('[') && trimmed.ends_with(']'))
+    }
+
+    fn display_follow_up_suggestions(&self, suggestions: &[String]) {
+        println!();
+        println!("{}", style("💡 Suggested next steps:").bold().fg(Color::Cyan));
+        
+        for (i, suggestion) in suggestions.iter().enumerate() {
+            println!(
+                "  {}  {}",
+                style(format!("{}.", i + 1)).fg(Color::Green),
+                self.format_command_with_syntax(suggestion)
+            );
+        }
+        
+        println!();
+    }
+
+    async fn handle_chat(&mut self, message: &str) -> Result<()> {
+        self.last_user_message = Some(message.to_string());
+        let response = match self.send_chat(message).await {
+            Ok(response) => response,
+            Err(e) if is_model_not_found(&e) => self.recover_from_missing_model(message, e).await?,
+            Err(e) => return Err(e),
+        };
+
+        if !self.dry_run && response.trim().is_empty() {
+            self.report_empty_response();
+            return Ok(());
+        }
+
+        // Prose-only answers leave nothing to run; ask again or point at /retry.
+        // A cancelled answer was stopped on purpose, so don't ask again
+        if !self.dry_run && self.last_suggested_commands.is_empty() && !self.client.was_cancelled() {
+            if self.config.auto_retry {
+                if !self.quiet {
+                    println!(
+                        "{} {}",
+                        style("•").fg(Color::Cyan),
+                        style("No runnable command found, asking for one...").dim()
+                    );
+                }
+                let response = self.send_chat(&Self::retry_message(message)).await?;
+                if response.trim().is_empty() {
+                    self.report_empty_response();
+                }
+            } else if !self.quiet {
+                println!(
+                    "{} {} {}",
+                    style("•").fg(Color::Cyan),
+                    style("No runnable command found. Type").dim(),
+                    style("/retry").fg(Color::Green)
+                );
+                println!(
+                    "  {}",
+                    style("to ask for an exact command instead.").dim()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `/nocache [question]`: ask the API even when `cache_answers` has an answer; the
+    /// new answer replaces the cached one
+    async fn ask_without_cache(&mut self, question: &str) -> Result<()> {
+        let message = match question {
+            "" => self.last_user_message.clone(),
+            question => Some(question.to_string()),
+        };
+        let Some(message) = message else {
+            println!(
+                "{} Nothing to ask again yet. Ask a question first.",
+                style("⚠").fg(Color::Yellow)
+            );
+            return Ok(());
+        };
+
+        self.client.skip_cache_once();
+        self.handle_chat(&message).await
+    }
+
+    async fn retry_last_message(&mut self) -> Result<()> {
+        let Some(message) = self.last_user_message.clone() else {
+            println!(
+                "{} Nothing to retry yet. Ask a question first.",
+                style("⚠").fg(Color::Yellow)
+            );
+            return Ok(());
+        };
+
+        let response = self.send_chat(&Self::retry_message(&message)).await?;
+        if !self.dry_run && response.trim().is_empty() {
+            self.report_empty_response();
+        }
+        Ok(())
+    }
+
+    /// Explain a blank answer instead of leaving the user staring at nothing
+    fn report_empty_response(&self) {
+        // Stopping before any text arrived was the user's choice, not a model problem
+        if self.client.was_cancelled() {
+            return;
+        }
+
+        let reason = match self.client.last_finish_reason().as_deref() {
+            Some("content_filter") => " (blocked by the provider's content filter)".to_string(),
+            Some("length") => " (it hit the token limit before writing anything)".to_string(),
+            Some("stop") | None => String::new(),
+            Some(other) => format!(" (finish reason: {})", other),
+        };
+
+        eprintln!(
+            "{} {}",
+            style("⚠").fg(Color::Yellow),
+            style(format!("The model returned an empty response{}.", reason)).fg(Color::Yellow)
+        );
+        eprintln!(
+            "  {} {} {} {}",
+            style("Try").dim(),
+            style("/retry").fg(Color::Green),
+            style("or switch models with").dim(),
+            style("/model").fg(Color::Green)
+        );
+    }
+
+    /// `/why [n]`: ask for a breakdown of a suggested command without running it. The
+    /// suggestions are left as they were, so `/run` still offers the same numbers
+    async fn explain_suggestion(&mut self, target: Option<&str>) -> Result<()> {
+        if self.last_suggested_commands.is_empty() {
+            println!(
+                "{} No suggested commands yet. Ask a question first.",
+                style("⚠").fg(Color::Yellow)
+            );
+            return Ok(());
+        }
+
+        let index = match target.map(str::parse::<usize>) {
+            None => Some(0),
+            Some(Ok(n)) => n.checked_sub(1),
+            Some(Err(_)) => None,
+        };
+        let Some(command) = index.and_then(|i| self.last_suggested_commands.get(i)).cloned() else {
+            println!(
+                "{} Pick a suggestion between 1 and {}, e.g. {}",
+                style("⚠").fg(Color::Yellow),
+                self.last_suggested_commands.len(),
+                style("/why 1").fg(Color::Green)
+            );
+            return Ok(());
+        };
+
+        println!();
+        println!("  {}", self.format_command_with_syntax(&command));
+
+        let message = why_message(&command);
+        let context = self.create_context(&message);
+        if self.dry_run {
+            return self.print_dry_run(WHY_INSTRUCTIONS, &context);
+        }
+
+        let response = self
+            .client
+            .stream_chat_with_timing(
+                Some(WHY_INSTRUCTIONS),
+                &context,
+                std::time::Instant::now(),
+                &message,
+            )
+            .await?;
+        if response.trim().is_empty() {
+            self.report_empty_response();
+        } else {
+            self.session.record(&message, &response, &self.client.answering_model());
+        }
+        Ok(())
+    }
+
+    /// `/summarize`: ask the model for the key points and errors in the last output
+    async fn summarize_last_output(&mut self) -> Result<()> {
+        let Some(captured) = self.last_output.clone() else {
+            println!(
+                "{} No command output yet. Run something first, e.g. {}",
+                style("⚠").fg(Color::Yellow),
+                style("/run journalctl -n 200").fg(Color::Green)
+            );
+            return Ok(());
+        };
+        if captured.output.trim().is_empty() {
+            println!(
+                "{} `{}` printed nothing to summarize.",
+                style("•").fg(Color::Cyan),
+                captured.command
+            );
+            return Ok(());
+        }
+
+        let mut message = summarize_message(&captured);
+        if self.config.redact_secrets {
+            message = self.redactor.redact(&message);
+        }
+        if captured.output.chars().count() > MAX_SUMMARY_CHARS && !self.quiet {
+            println!(
+                "{}",
+                style(format!(
+                    "Output is long; sending its last {} characters.",
+                    MAX_SUMMARY_CHARS
+                ))
+                .dim()
+            );
+        }
+        if self.dry_run {
+            return self.print_dry_run(SUMMARIZE_INSTRUCTIONS, &message);
+        }
+
+        let response = self
+            .client
+            .stream_chat_with_timing(
+                Some(SUMMARIZE_INSTRUCTIONS),
+                &message,
+                std::time::Instant::now(),
+                &message,
+            )
+            .await?;
+        if response.trim().is_empty() {
+            self.report_empty_response();
+        } else {
+            self.session.record(
+                &format!("/summarize {}", captured.command),
+                &response,
+                &self.client.answering_model(),
+            );
+        }
+        Ok(())
+    }
+
+    fn retry_message(message: &str) -> String {
+        format!(
+            "{}\n\nRespond with an exact runnable shell command in backticks, not just an explanation.",
+            message
+        )
+    }
+
+    /// Send one request and handle the answer; returns the response text (empty for
+    /// dry runs and blank answers)
+    async fn send_chat(&mut self, message: &str) -> Result<String> {
+        use std::time::Instant;
+
+        // Start timing
+        let start_time = Instant::now();
+
+        // Create enriched context with environment info
+        let context = self.create_context(message);
+        let system_prompt = self.system_prompt();
+
+        if self.dry_run {
+            self.print_dry_run(&system_prompt, &context)?;
+            return Ok(String::new());
+        }
+
+        let response = self
+            .client
+            .stream_chat_with_timing(Some(&system_prompt), &context, start_time, message)
+            .await?;
+
+        if response.trim().is_empty() {
+            self.last_suggested_commands.clear();
+            return Ok(response);
+        }
+
+        self.session.record(message, &response, &self.client.answering_model());
+        self.finish_response(&response).await;
+
+        Ok(response)
+    }
+
+    /// Shared tail of an answer: remember its commands, mention a cut-off answer and
+    /// offer the command menu
+    async fn finish_response(&mut self, response: &str) {
+        // Extract commands from response for quick execution
+        self.extract_and_store_commands(response);
+
+        self.truncated = self.client.last_finish_reason().as_deref() == Some("length");
+        if self.truncated && !self.quiet {
+            println!(
+                "{} {} {} {}",
+                style("•").fg(Color::Cyan),
+                style("The answer was cut off at the model's length limit. Type").dim(),
+                style("/continue").fg(Color::Green),
+                style("for the rest.").dim()
+            );
+        }
+
+        // Auto-trigger interactive menu if commands were suggested (never in quiet mode,
+        // which is meant for scripts). After Ctrl-C the partial commands stay available
+        // through /run instead of popping up a menu
+        if !self.quiet && !self.client.was_cancelled() && self.should_show_menu() {
+            // Small delay to ensure terminal state is stable after response printing
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            
+            if let Err(e) = self.prompt_command_selection().await {
+                eprintln!(
+                    "{} Error in command selection: {}",
+                    style("✗").fg(Color::Red),
+                    style(e).fg(Color::Red)
+                );
+            }
+        }
+    }
+
+    /// `/good` / `/bad`: store the last question and answer with a rating
+    fn rate_last_answer(&self, rating: Rating) -> Result<()> {
+        let Some(turn) = self.session.turns.last() else {
+            println!(
+                "{} Nothing to rate yet. Ask a question first.",
+                style("⚠").fg(Color::Yellow)
+            );
+            return Ok(());
+        };
+
+        // Rate the model that actually answered, not the one asked for
+        let model = match turn.model.as_str() {
+            "" => self.client.model(),
+            model => model,
+        };
+        let entry = Feedback::new(model, &turn.question, &turn.answer, rating);
+        feedback::append(&feedback::feedback_path()?, &entry)?;
+        let label = match rating {
+            Rating::Good => "👍 Marked as good",
+            Rating::Bad => "👎 Marked as bad",
+        };
+        println!(
+            "{} {}",
+            label,
+            style(format!("({}; see /ratings)", entry.model)).dim()
+        );
+        Ok(())
+    }
+
+    fn show_ratings(&self) -> Result<()> {
+        let path = feedback::feedback_path()?;
+        let tallies = feedback::summarize(&path)?;
+
+        println!();
+        if tallies.is_empty() {
+            println!(
+                "{} No ratings yet. Use {} or {} after an answer.",
+                style("ℹ").fg(Color::Blue),
+                style("/good").fg(Color::Green),
+                style("/bad").fg(Color::Green)
+            );
+            println!();
+            return Ok(());
+        }
+
+        println!("{}", style("Ratings by model").bold().fg(Color::Cyan));
+        for (model, tally) in &tallies {
+            println!(
+                "  {:<32} {} {}  {}",
+                model,
+                style(format!("👍 {}", tally.good)).fg(Color::Green),
+                style(format!("👎 {}", tally.bad)).fg(Color::Red),
+                style(format!("{}% good", tally.good_percent())).dim()
+            );
+        }
+        println!("  {}", style(path.display()).dim());
+        println!();
+        Ok(())
+    }
+
+    /// `/continue`: send the cut-off answer back and ask the model to pick up where it
+    /// stopped. The continuation is appended to the same turn of the session
+    async fn continue_response(&mut self) -> Result<()> {
+        let last_turn = self.session.turns.last().cloned();
+        let Some(turn) = last_turn.filter(|_| self.truncated) else {
+            println!(
+                "{} Nothing to continue: the last answer was complete.",
+                style("ℹ").fg(Color::Blue)
+            );
+            return Ok(());
+        };
+
+        let message = continue_message(&turn.question, &turn.answer);
+        let system_prompt = self.system_prompt();
+        if self.dry_run {
+            return self.print_dry_run(&system_prompt, &message);
+        }
+
+        let continuation = self
+            .client
+            .stream_chat_with_timing(
+                Some(&system_prompt),
+                &message,
+                std::time::Instant::now(),
+                &message,
+            )
+            .await?;
+        if continuation.trim().is_empty() {
+            self.report_empty_response();
+            return Ok(());
+        }
+
+        self.session.extend_last_answer(&continuation);
+        let full_answer = format!("{}{}", turn.answer, continuation);
+        self.finish_response(&full_answer).await;
+        Ok(())
+    }
+
+    /// Chat answers often quote things that merely look like commands; in `auto` mode
+    /// only interrupt the user when something in the list can really be run
+    fn should_show_menu(&self) -> bool {
+        match self.config.suggestion_menu {
+            SuggestionMenu::Never => {
+                if !self.last_suggested_commands.is_empty() {
+                    println!(
+                        "{} {} {}",
+                        style("•").fg(Color::Cyan),
+                        style("Type").dim(),
+                        style("/run").fg(Color::Green)
+                    );
+                    println!("  {}", style("to pick one of the suggested commands.").dim());
+                }
+                false
+            }
+            SuggestionMenu::Always => !self.last_suggested_commands.is_empty(),
+            SuggestionMenu::Auto => self.last_suggested_commands.iter().any(|c| is_runnable(c)),
+        }
+    }
+
+    fn print_dry_run(&self, system_prompt: &str, context: &str) -> Result<()> {
+        println!();
+        println!(
+            "{} {}",
+            style("Dry run").bold().fg(Color::Cyan),
+            style("(no request sent)").dim()
+        );
+        println!(
+            "  {}: {}",
+            style("Model").fg(Color::Green),
+            style(self.client.model()).fg(Color::White)
+        );
+        println!();
+        println!("{}", style("System prompt:").bold().fg(Color::Green));
+        println!("{}", system_prompt);
+        println!();
+        println!("{}", style("Message:").bold().fg(Color::Green));
+        println!("{}", context);
+        println!();
+        println!("{}", style("Payload:").bold().fg(Color::Green));
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&self.client.build_payload(Some(system_prompt), context))?
+        );
+        println!();
+        Ok(())
+    }
+
+    fn create_context(&self, message: &str) -> String {
+        let files = self.file_context_blocks(message);
+        let assemble = |kept_files: usize, kept_history: Option<usize>| {
+            let mut context = self.build_environment_context(kept_history);
+            context.push('\n');
+            context.push_str(&self.join_file_context(&files[..kept_files]));
+            context.push_str(&self.variable_context(message));
+            context.push_str("User request: ");
+            context.push_str(message);
+            context
+        };
+
+        let context = assemble(files.len(), None);
+        let Some(limit) = self.context_limit else {
+            return context;
+        };
+        let budget = context_window::request_budget(limit);
+        let system = context_window::estimate_tokens(&self.system_prompt());
+        let total = system + context_window::estimate_tokens(&context);
+        if total <= budget {
+            return context;
+        }
+
+        // Leave out files, mentioned ones before attachments, then the oldest history
+        let file_tokens: Vec<usize> = files.iter().map(|f| context_window::estimate_tokens(f)).collect();
+        let history_tokens: Vec<usize> = self
+            .cached_history()
+            .iter()
+            .map(|cmd| context_window::estimate_tokens(cmd) + 2)
+            .collect();
+        let optional: usize = file_tokens.iter().chain(&history_tokens).sum();
+        let (kept_files, kept_history) = context_window::fit_context(
+            total.saturating_sub(optional),
+            &file_tokens,
+            &history_tokens,
+            budget,
+        );
+        let context = assemble(kept_files, Some(kept_history));
 
-            if modify {
-                current_command = Input::new()
-                    .with_prompt("Enter modified command")
-                    .with_initial_text(&current_command)
-                    .interact_text()?;
-            } else {
-                println!("{}", style("Command cancelled.").fg(Color::Yellow));
-                return Ok(None);
+        if !self.quiet {
+            let mut left_out = Vec::new();
+            if kept_files < files.len() {
+                let n = files.len() - kept_files;
+                left_out.push(format!("{} file{}", n, if n == 1 { "" } else { "s" }));
+            }
+            if kept_history < history_tokens.len() {
+                let n = history_tokens.len() - kept_history;
+                left_out.push(format!("{} history line{}", n, if n == 1 { "" } else { "s" }));
             }
+            let mut warning = format!(
+                "The request is close to {}'s {}-token context window",
+                self.client.model(),
+                limit
+            );
+            if !left_out.is_empty() {
+                warning.push_str(&format!("; left out {}", left_out.join(" and ")));
+            }
+            if system + context_window::estimate_tokens(&context) > budget {
+                warning.push_str("; the question may still be too long for this model");
+            }
+            eprintln!(
+                "{} {}",
+                style("⚠").for_stderr().fg(Color::Yellow),
+                style(warning).for_stderr().fg(Color::Yellow)
+            );
         }
+        context
     }
 
-    fn display_command_preview(&self, command: &str) {
-        println!();
-        println!("{}", style("Command Execution").bold().fg(Color::Cyan));
-        println!(
-            "{} {}",
-            style("•").fg(Color::Green),
-            style("Executing shell command as requested").dim()
-        );
-        println!();
-        println!("{}", style("Command:").bold().fg(Color::Green));
-        println!("  {}", self.format_command_with_syntax(command));
-        println!();
+    fn build_context_prefix(&self) -> String {
+        let mut context = self.build_environment_context(None);
+        context.push('\n');
+        context
     }
 
-    fn run_system_command(&self, command: &str) -> Result<()> {
-        use std::process::Command;
+    /// History lines from the last context scan
+    fn cached_history(&self) -> Vec<String> {
+        let cache = self.context_cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.as_ref().map(|cached| cached.history.clone()).unwrap_or_default()
+    }
 
-        println!(
-            "{} {}",
-            style("▸").fg(Color::Green),
-            style(command).bold()
-        );
+    /// Instructions sent as the `system` message: `system_prompt` from the config (often
+    /// a project's), then prompt.md, then the `prompt_url` template, else the defaults
+    fn system_prompt(&self) -> String {
+        self.config
+            .system_prompt
+            .clone()
+            .filter(|prompt| !prompt.trim().is_empty())
+            .or_else(Config::load_custom_prompt)
+            .or_else(|| self.shared_prompt.clone())
+            .unwrap_or_else(|| Self::default_instructions(self.config.max_suggestions))
+    }
 
-        let output = if cfg!(target_os = "windows") {
-            Command::new("cmd").args(["/C", command]).output()
-        } else {
-            Command::new("sh").arg("-c").arg(command).output()
+    /// Network lookups done once per session: the shared prompt and the context window
+    async fn start_session(&mut self) {
+        self.load_shared_prompt().await;
+        self.load_context_limit().await;
+    }
+
+    /// Look up the current model's context window, from the cached model list when it
+    /// is recent enough. Unknown limits leave the context untrimmed
+    async fn load_context_limit(&mut self) {
+        self.context_limit = None;
+        if self.dry_run {
+            return;
+        }
+        let Ok(path) = context_window::cache_path() else {
+            return;
         };
 
-        match output {
-            Ok(output) => {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                
-                if !stdout.is_empty() {
-                    println!("{}", stdout);
-                }
-                if !stderr.is_empty() {
-                    eprintln!("{}", stderr);
-                }
-                
-                if output.status.success() {
-                    // Analyze output for intelligent follow-up suggestions
-                    if let Some(suggestions) = self.analyze_command_output(command, &stdout) {
-                        self.display_follow_up_suggestions(&suggestions);
-                    }
-                } else {
-                    println!(
-                        "{} Command exited with status: {}",
-                        style("⚠").fg(Color::Yellow),
-                        style(output.status).fg(Color::Red)
-                    );
-                }
-            }
-            Err(e) => {
-                eprintln!(
-                    "{} Failed to execute command: {}",
-                    style("✗").fg(Color::Red),
-                    style(e).fg(Color::Red)
-                );
-            }
+        let limits = context_window::load(&self.client, &path).await;
+        self.context_limit = limits.and_then(|limits| limits.get(self.client.model()).copied());
+    }
+
+    /// Fetch the team's `prompt_url` template (or reuse the cached copy) for this session
+    async fn load_shared_prompt(&mut self) {
+        let Some(url) = self.config.prompt_url.clone() else {
+            return;
+        };
+        let Ok(cache) = shared_prompt::cache_path(&url) else {
+            return;
+        };
+        let ttl = Duration::from_secs(self.config.prompt_url_ttl_secs);
+        self.shared_prompt = shared_prompt::load(&self.client, &url, ttl, &cache).await;
+        if self.shared_prompt.is_none() && !self.quiet {
+            eprintln!(
+                "{} {}",
+                style("⚠").for_stderr().fg(Color::Yellow),
+                style(format!("Could not load prompt_url {}, using the built-in instructions", url))
+                    .for_stderr()
+                    .dim()
+            );
         }
+    }
 
-        Ok(())
+    pub(crate) fn default_instructions(max_suggestions: usize) -> String {
+        let numbering = (1..=max_suggestions)
+            .map(|n| format!("{}.", n))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut instructions = String::new();
+        instructions.push_str("Instructions: You are a professional shell assistant. Provide concise, helpful responses.\n");
+        instructions.push_str("Response format:\n");
+        instructions.push_str(&format!(
+            "- NUMBER your suggestions as {} to match the execution menu\n",
+            numbering
+        ));
+        if max_suggestions == 1 {
+            instructions.push_str("- Suggest only the single best solution\n");
+        } else {
+            instructions.push_str(&format!(
+                "- Suggest 1-{} different solutions with varied approaches\n",
+                max_suggestions
+            ));
+        }
+        instructions.push_str("- Vary your language - don't repeat the same starting phrases\n");
+        instructions.push_str("- Be more descriptive about what each command accomplishes\n");
+        instructions.push_str("- Examples: '1. Show basic listing', '2. Display detailed file info', '3. View hidden files and permissions'\n");
+        instructions.push_str("- Put commands and flags in backticks: `ls`, `-la`, `git status`\n");
+        instructions.push_str("- NO emojis - maintain professional CLI aesthetic\n");
+        instructions.push_str("- Keep explanations brief but informative\n");
+        instructions
+            .push_str("- Consider the user's recent command history when suggesting solutions\n");
+
+        instructions
     }
 
-    fn analyze_command_output(&self, command: &str, output: &str) -> Option<Vec<String>> {
-        let mut suggestions = Vec::new();
-        
-        // XKCD API detection
-        if command.contains("xkcd.com") && command.contains("info.0.json") {
-            if let Some(download_cmd) = self.extract_xkcd_download_suggestion(output) {
-                suggestions.push(download_cmd);
+    /// Inline the contents of attached files and of files the user mentions, clearly
+    /// delimited, one block per file. Attachments come first, so trimming to fit the
+    /// context window drops mentioned files before them
+    pub(crate) fn file_context_blocks(&self, message: &str) -> Vec<String> {
+        // Attachments were asked for explicitly, so `send_files = false` doesn't hold them back
+        let mut blocks = Vec::new();
+        let mut attached_bytes = 0;
+        for path in &self.attachments {
+            let Some((contents, truncated)) = read_text_for_context(path) else {
+                log::warn!("Attached file {} can no longer be read", path.display());
+                continue;
+            };
+            // Files can grow after `/attach` checked the budget
+            attached_bytes += contents.len();
+            if attached_bytes > MAX_ATTACHED_BYTES {
+                log::warn!(
+                    "Attached file {} left out: attachments exceed {} bytes",
+                    path.display(),
+                    MAX_ATTACHED_BYTES
+                );
+                attached_bytes -= contents.len();
+                continue;
             }
+            blocks.push(file_block(&self.file_display_name(path), &contents, truncated));
         }
-        
-        // JSON API responses with downloadable content
-        if self.looks_like_json(output) {
-            if let Some(download_cmd) = self.extract_download_from_json(output) {
-                suggestions.push(download_cmd);
+
+        if !self.config.send_files {
+            return blocks;
+        }
+
+        let Ok(cwd) = env::current_dir() else {
+            return blocks;
+        };
+
+        for path in referenced_files(message, &cwd) {
+            if self.attachments.contains(&path) {
+                continue;
             }
+            let Some((contents, truncated)) = read_text_for_context(&path) else {
+                continue;
+            };
+            blocks.push(file_block(&self.file_display_name(&path), &contents, truncated));
         }
-        
-        // File listings that could benefit from filtering/sorting
-        if command.starts_with("ls") && output.lines().count() > 10 {
-            suggestions.push("Filter results with: ls | grep <pattern>".to_string());
-            suggestions.push("Sort by date: ls -lt".to_string());
+        blocks
+    }
+
+    /// A path relative to the working directory when it is inside it
+    fn file_display_name(&self, path: &Path) -> String {
+        env::current_dir()
+            .and_then(|cwd| cwd.canonicalize())
+            .ok()
+            .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+            .unwrap_or_else(|| path.to_path_buf())
+            .display()
+            .to_string()
+    }
+
+    /// Add a file to `attachments`, checking that it is readable text and that all
+    /// attachments together stay within `MAX_ATTACHED_BYTES`
+    pub(crate) fn attach(&mut self, arg: &str) -> std::result::Result<(PathBuf, bool), String> {
+        let words = shlex::split(arg).unwrap_or_default();
+        let [path] = words.as_slice() else {
+            return Err("Expected one path (quote paths with spaces)".to_string());
+        };
+        let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(path),
+        };
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if !path.is_file() {
+            return Err(format!("{} is not a file", path.display()));
         }
-        
-        // Git commands that often have follow-ups
-        if command.starts_with("git status") && output.contains("modified:") {
-            suggestions.push("git diff".to_string());
-            suggestions.push("git add .".to_string());
+        let Some((contents, truncated)) = read_text_for_context(&path) else {
+            return Err(format!("{} looks like a binary file", path.display()));
+        };
+        let attached_bytes: usize = self
+            .attachments
+            .iter()
+            .filter(|attached| **attached != path)
+            .filter_map(|attached| read_text_for_context(attached))
+            .map(|(contents, _)| contents.len())
+            .sum();
+        if attached_bytes + contents.len() > MAX_ATTACHED_BYTES {
+            return Err(format!(
+                "{} would take attachments over {} KB; /detach a file first",
+                path.display(),
+                MAX_ATTACHED_BYTES / 1024
+            ));
         }
-        
-        if suggestions.is_empty() {
-            None
-        } else {
-            Some(suggestions)
+
+        if !self.attachments.contains(&path) {
+            self.attachments.push(path.clone());
         }
+        Ok((path, truncated))
     }
 
-    fn extract_xkcd_download_suggestion(&self, output: &str) -> Option<String> {
-        
-        // Parse JSON to extract img URL and title
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
-            let img_url = json["img"].as_str()?;
-            let title = json["title"].as_str().unwrap_or("comic");
-            let safe_title = json["safe_title"].as_str().unwrap_or(title);
-            
-            // Extract filename from URL
-            if let Some(filename) = img_url.split('/').last() {
-                return Some(format!(
-                    "curl -o '{}.{}' '{}'", 
-                    safe_title, 
-                    filename.split('.').last().unwrap_or("png"),
-                    img_url
-                ));
-            }
-        }
-        
-        None
+    /// Remove the attachment matching `arg` (as attached, or by file name), or all of them
+    pub(crate) fn detach(&mut self, arg: Option<&str>) -> Vec<PathBuf> {
+        let Some(arg) = arg else {
+            return std::mem::take(&mut self.attachments);
+        };
+        let target = Path::new(arg).canonicalize().ok();
+        let (removed, kept) = std::mem::take(&mut self.attachments)
+            .into_iter()
+            .partition(|path| {
+                Some(path) == target.as_ref()
+                    || self.file_display_name(path) == arg
+                    || path.file_name().is_some_and(|name| name == arg)
+            });
+        self.attachments = kept;
+        removed
     }
 
-    fn extract_download_from_json(&self, output: &str) -> Option<String> {
-        // Look for common downloadable file patterns in JSON
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
-            // Check for various download URL patterns
-            for key in &["img", "image", "url", "download_url", "file", "src"] {
-                if let Some(url) = json[key].as_str() {
-                    if self.is_downloadable_url(url) {
-                        if let Some(filename) = url.split('/').last() {
-                            return Some(format!("curl -o '{}' '{}'", filename, url));
-                        }
-                    }
+    fn attach_file(&mut self, arg: &str) {
+        match self.attach(arg) {
+            Ok((path, truncated)) => {
+                println!(
+                    "{} {} will be sent with every message until /detach",
+                    style("✓").fg(Color::Green),
+                    style(self.file_display_name(&path)).bold()
+                );
+                if truncated {
+                    println!(
+                        "  {}",
+                        style(format!("Only the first {} bytes are sent", MAX_REFERENCED_FILE_BYTES)).dim()
+                    );
                 }
             }
+            Err(message) => println!("{} {}", style("✗").fg(Color::Red), message),
         }
-        None
-    }
-
-    fn is_downloadable_url(&self, url: &str) -> bool {
-        let downloadable_extensions = [
-            ".png", ".jpg", ".jpeg", ".gif", ".pdf", ".zip", 
-            ".mp4", ".mp3", ".doc", ".txt", ".svg"
-        ];
-        
-        downloadable_extensions.iter().any(|ext| url.ends_with(ext))
     }
 
-    fn looks_like_json(&self, text: &str) -> bool {
-        let trimmed = text.trim();
-        (trimmed.starts_with('{') && trimmed.ends_with('}')) ||
-        (trimmed.starts_with('[') && trimmed.ends_with(']'))
+    fn detach_files(&mut self, arg: Option<&str>) {
+        let removed = self.detach(arg);
+        if removed.is_empty() {
+            println!("{} Nothing attached matches.", style("•").fg(Color::Cyan));
+        }
+        for path in removed {
+            println!(
+                "{} {} detached",
+                style("✓").fg(Color::Green),
+                self.file_display_name(&path)
+            );
+        }
     }
 
-    fn display_follow_up_suggestions(&self, suggestions: &[String]) {
-        println!();
-        println!("{}", style("💡 Suggested next steps:").bold().fg(Color::Cyan));
-        
-        for (i, suggestion) in suggestions.iter().enumerate() {
+    fn list_attachments(&self) {
+        if self.attachments.is_empty() {
+            println!("{} No files attached.", style("•").fg(Color::Cyan));
             println!(
-                "  {}  {}",
-                style(format!("{}.", i + 1)).fg(Color::Green),
-                self.format_command_with_syntax(suggestion)
+                "  {} {}",
+                style("/attach").fg(Color::Green),
+                style("<path>").dim()
             );
+            return;
+        }
+        println!("{}", style("Attached files").bold().fg(Color::Cyan));
+        for path in &self.attachments {
+            println!("  {} {}", style("•").fg(Color::Cyan), self.file_display_name(path));
         }
-        
-        println!();
     }
 
-    async fn handle_chat(&mut self, message: &str) -> Result<()> {
-        use std::time::Instant;
-
-        // Start timing
-        let start_time = Instant::now();
-
-        // Create enriched context with environment info
-        let context = self.create_context(message);
-        let response = self
-            .client
-            .stream_chat_with_timing(&context, start_time, message)
-            .await?;
+    /// Values of the session variables the message mentions, such as output kept with
+    /// `/run --capture`, each cut to `MAX_REFERENCED_FILE_BYTES`
+    fn variable_context(&self, message: &str) -> String {
+        let mut blocks = String::new();
+        for name in mentioned_variables(message, self.session_env.keys()) {
+            let value = &self.session_env[name];
+            let mut end = value.len().min(MAX_REFERENCED_FILE_BYTES);
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            blocks.push_str(&format!("----- BEGIN ${} -----\n{}\n", name, &value[..end]));
+            if end < value.len() {
+                blocks.push_str(&format!(
+                    "[... truncated after {} bytes ...]\n",
+                    MAX_REFERENCED_FILE_BYTES
+                ));
+            }
+            blocks.push_str(&format!("----- END ${} -----\n\n", name));
+        }
+        if blocks.is_empty() {
+            return blocks;
+        }
 
-        // Extract commands from response for quick execution
-        self.extract_and_store_commands(&response);
+        let context = format!("Session variables mentioned in the request:\n{}", blocks);
+        if self.config.redact_secrets {
+            self.redactor.redact(&context)
+        } else {
+            context
+        }
+    }
 
-        // Auto-trigger interactive menu if commands were suggested
-        if !self.last_suggested_commands.is_empty() {
-            // Small delay to ensure terminal state is stable after response printing
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            
-            if let Err(e) = self.prompt_command_selection().await {
-                eprintln!(
-                    "{} Error in command selection: {}",
-                    style("✗").fg(Color::Red),
-                    style(e).fg(Color::Red)
-                );
-            }
+    fn join_file_context(&self, blocks: &[String]) -> String {
+        if blocks.is_empty() {
+            return String::new();
         }
 
-        Ok(())
+        let context = format!("Referenced files:\n{}", blocks.concat());
+        if self.config.redact_secrets {
+            self.redactor.redact(&context)
+        } else {
+            context
+        }
     }
 
-    fn create_context(&self, message: &str) -> String {
+    /// `kept_history` limits the shell history to that many of the most recent entries
+    fn build_environment_context(&self, kept_history: Option<usize>) -> String {
         let mut context = String::new();
 
         // Add environment context
@@ -681,104 +3766,107 @@ impl ShyRepl {
             context.push_str(&format!("Shell: {}\n", shell));
         }
 
-        // Add some files for context (limited)
-        if let Ok(entries) = fs::read_dir(".") {
-            let files: Vec<_> = entries
-                .filter_map(|entry| entry.ok())
-                .filter_map(|entry| entry.file_name().into_string().ok())
-                .take(5)
-                .collect();
+        let mut cache = self.context_cache.lock().unwrap_or_else(|e| e.into_inner());
+        let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let scanned = match cache.take() {
+            Some(cached) if cached.is_fresh(&cwd, std::time::Instant::now()) => cached,
+            _ => self.scan_context(cwd),
+        };
 
-            if !files.is_empty() {
-                context.push_str("Files in current directory: ");
-                context.push_str(&files.join(", "));
-                context.push('\n');
-            }
+        // Add some files for context (limited, unless disabled for privacy)
+        if !scanned.files.is_empty() {
+            context.push_str("Files in current directory: ");
+            context.push_str(&scanned.files.join(", "));
+            context.push('\n');
         }
 
-        // Add recent shell history for context
-        if let Ok((recent_commands, _)) = self.get_recent_bash_history(10) {
-            if !recent_commands.is_empty() {
-                context.push_str("Recent shell history:\n");
-                for (i, cmd) in recent_commands.iter().enumerate() {
-                    context.push_str(&format!("  {}: {}\n", i + 1, cmd));
-                }
+        // Nudge suggestions towards the project's own tooling
+        if !scanned.project_types.is_empty() {
+            context.push_str(&format!("Project type: {}\n", scanned.project_types.join(", ")));
+        }
+
+        // Add recent shell history for context (unless disabled for privacy)
+        let skipped = scanned.history.len() - kept_history.unwrap_or(usize::MAX).min(scanned.history.len());
+        let history = &scanned.history[skipped..];
+        if !history.is_empty() {
+            context.push_str("Recent shell history:\n");
+            for (i, cmd) in history.iter().enumerate() {
+                context.push_str(&format!("  {}: {}\n", i + 1, cmd));
             }
         }
+        *cache = Some(scanned);
+        drop(cache);
 
         context.push_str(&format!("OS: {}\n", env::consts::OS));
-        context.push('\n');
-        context.push_str("Instructions: You are a professional shell assistant. Provide concise, helpful responses.\n");
-        context.push_str("Response format:\n");
-        context.push_str("- NUMBER your suggestions as 1., 2., 3. to match the execution menu\n");
-        context.push_str("- Suggest 1-3 different solutions with varied approaches\n");
-        context.push_str("- Vary your language - don't repeat the same starting phrases\n");
-        context.push_str("- Be more descriptive about what each command accomplishes\n");
-        context.push_str("- Examples: '1. Show basic listing', '2. Display detailed file info', '3. View hidden files and permissions'\n");
-        context.push_str("- Put commands and flags in backticks: `ls`, `-la`, `git status`\n");
-        context.push_str("- NO emojis - maintain professional CLI aesthetic\n");
-        context.push_str("- Keep explanations brief but informative\n");
-        context
-            .push_str("- Consider the user's recent command history when suggesting solutions\n\n");
-        context.push_str("User request: ");
-        context.push_str(message);
-
-        context
-    }
-
-    fn extract_and_store_commands(&mut self, response: &str) {
-        use regex::Regex;
-
-        let mut commands = Vec::new();
 
-        // Extract from numbered lists (most common pattern in AI responses)
-        if let Ok(numbered_regex) = Regex::new(r"^\d+\.\s*(?:[^:]+:\s*)?(.+)$") {
-            for line in response.lines() {
-                if let Some(cap) = numbered_regex.captures(line.trim()) {
-                    if let Some(command_desc) = cap.get(1) {
-                        let desc = command_desc.as_str().trim();
-                        // Extract the actual command from the description
-                        if let Some(cmd) = self.extract_command_from_description(desc) {
-                            commands.push(cmd);
-                        }
-                    }
-                }
-            }
+        let tools = preferred_tools(&self.config.preferred_tools, self.config.detect_tools);
+        if !tools.is_empty() {
+            context.push_str(&format!(
+                "Available tools: prefer {} where they fit\n",
+                tools.join(", ")
+            ));
         }
 
-        // Extract from code blocks
-        if let Ok(code_block_regex) = Regex::new(r"```(?:bash|sh|shell)?\n([^`]+)```") {
-            for cap in code_block_regex.captures_iter(response) {
-                if let Some(command) = cap.get(1) {
-                    let cmd = command.as_str().trim();
-                    if !cmd.is_empty() && Self::looks_like_command(cmd) {
-                        commands.push(cmd.to_string());
-                    }
-                }
-            }
+        // Scrub secrets from history and file names before they leave the machine
+        if self.config.redact_secrets {
+            self.redactor.redact(&context)
+        } else {
+            context
         }
+    }
 
-        // Extract from inline code - use extended matching for complex commands
-        if let Ok(inline_code_regex) = Regex::new(r"`([^`]+)`") {
-            for cap in inline_code_regex.captures_iter(response) {
-                if let Some(command) = cap.get(1) {
-                    let cmd = command.as_str().trim();
-                    // Use extended matching to capture complex commands with pipes
-                    if Self::looks_like_command_extended(cmd) {
-                        commands.push(cmd.to_string());
-                    }
-                }
-            }
+    /// Read the directory listing and shell history, skipping what privacy settings exclude
+    fn scan_context(&self, dir: PathBuf) -> ContextCache {
+        let (files, project_types) = if self.config.send_files {
+            let files = fs::read_dir(&dir)
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .filter_map(|entry| entry.file_name().into_string().ok())
+                        .take(5)
+                        .collect()
+                })
+                .unwrap_or_default();
+            (files, detect_project_types(&dir))
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        let history = if self.config.send_history {
+            self.get_recent_bash_history(10)
+                .map(|(commands, _)| commands)
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        ContextCache {
+            dir,
+            scanned_at: std::time::Instant::now(),
+            files,
+            project_types,
+            history,
         }
+    }
 
-        // Limit to 3 commands max
-        commands.truncate(3);
-        self.last_suggested_commands = commands;
+    /// Forget the cached directory listing and history so the next message re-scans
+    fn refresh_context(&self) {
+        *self.context_cache.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        println!(
+            "{} Directory listing and shell history will be re-read",
+            style("✓").fg(Color::Green)
+        );
+    }
 
+    fn extract_and_store_commands(&mut self, response: &str) {
         // Commands will be shown in the interactive menu
+        self.last_suggested_commands = match self.client.structured_commands() {
+            Some(commands) => dedupe_commands(commands, self.config.max_suggestions),
+            None => extract_commands(response, self.config.max_suggestions),
+        };
     }
 
-    fn extract_command_from_description(&self, description: &str) -> Option<String> {
+    fn extract_command_from_description(description: &str) -> Option<String> {
         use regex::Regex;
         
         // First, try to extract commands from backticks within the description
@@ -827,54 +3915,98 @@ impl ShyRepl {
 
         for (i, cmd) in self.last_suggested_commands.iter().enumerate() {
             let formatted_cmd = self.format_command_with_syntax(cmd);
-            println!(
-                "{}  {}",
-                style(format!("[{}]", i + 1)).bold().fg(Color::Green),
-                formatted_cmd
-            );
+            match Self::missing_program(cmd) {
+                Some(program) => {
+                    println!(
+                        "{}  {} {}",
+                        style(format!("[{}]", i + 1)).bold().fg(Color::Green),
+                        formatted_cmd,
+                        style("(not installed)").dim()
+                    );
+                    if let Some(hint) = install_hint(program) {
+                        println!(
+                            "     {} {}",
+                            style("install with:").dim(),
+                            self.format_command_with_syntax(&hint)
+                        );
+                    }
+                }
+                None => {
+                    println!(
+                        "{}  {}",
+                        style(format!("[{}]", i + 1)).bold().fg(Color::Green),
+                        formatted_cmd
+                    );
+                }
+            }
         }
         println!();
     }
 
+    /// The program a suggested command needs, if it is not available on PATH
+    fn missing_program(command: &str) -> Option<&str> {
+        command_program(command).filter(|program| !is_installed(program))
+    }
+
     fn format_command_with_syntax(&self, cmd: &str) -> String {
-        let parts: Vec<&str> = cmd.split_whitespace().collect();
-        if parts.is_empty() {
+        if cmd.trim().is_empty() {
             return cmd.to_string();
         }
+        highlight_command(cmd)
+    }
+
+    async fn prompt_command_selection(&mut self) -> Result<()> {
+        if self.last_suggested_commands.is_empty() {
+            return Ok(());
+        }
 
-        let mut result = String::new();
+        // Number keys only reach 9; longer lists get the regular menu
+        let keys = self.config.ui.menu_style == MenuStyle::Keys
+            && self.last_suggested_commands.len() <= 9
+            && console::Term::stdout().is_term();
+        let choice = if keys {
+            self.pick_suggestion_by_key()?
+        } else {
+            self.pick_suggestion_from_menu()?
+        };
 
-        // Command name in cyan
-        result.push_str(&style(&parts[0]).fg(Color::Cyan).to_string());
+        match choice {
+            MenuChoice::Skip => {}
+            MenuChoice::Run(i) => {
+                let command = self.last_suggested_commands[i].clone();
+                self.execute_command_with_confirmation(&command, false, CommandSource::Suggestion)
+                    .await?;
+            }
+            MenuChoice::Custom => {
+                use dialoguer::Input;
+                let custom_command = Input::<String>::new()
+                    .with_prompt("Enter your command")
+                    .interact_text();
+                let custom_command = cancellable(custom_command)?.unwrap_or_default();
 
-        // Flags and arguments
-        for part in &parts[1..] {
-            result.push(' ');
-            if part.starts_with('-') {
-                // Flags in yellow
-                result.push_str(&style(part).fg(Color::Yellow).to_string());
-            } else {
-                // Arguments in white
-                result.push_str(&style(part).fg(Color::White).to_string());
+                if !custom_command.trim().is_empty() {
+                    self.execute_command(&custom_command).await?;
+                } else {
+                    println!("{}", style("No command entered.").fg(Color::Green));
+                }
             }
         }
 
-        result
+        Ok(())
     }
 
-    async fn prompt_command_selection(&mut self) -> Result<()> {
-        use dialoguer::{theme::ColorfulTheme, Select};
-
-        if self.last_suggested_commands.is_empty() {
-            return Ok(());
-        }
-
+    fn pick_suggestion_from_menu(&self) -> Result<MenuChoice> {
         // Create menu options with "Do nothing" as first option
         let mut menu_options = vec!["Do nothing".to_string()];
 
         for (i, cmd) in self.last_suggested_commands.iter().enumerate() {
             // Use plain text for menu items to avoid ANSI conflicts with dialoguer
-            menu_options.push(format!("Execute {}: {}", i + 1, cmd));
+            let marker = if Self::missing_program(cmd).is_some() {
+                " (not installed)"
+            } else {
+                ""
+            };
+            menu_options.push(format!("Execute {}: {}{}", i + 1, cmd, marker));
         }
 
         menu_options.push("Enter custom command".to_string());
@@ -884,38 +4016,54 @@ impl ShyRepl {
         io::stdout().flush().unwrap();
         
         println!(); // Add spacing before menu
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("What would you like to do?")
-            .default(0) // Default to "Do nothing" for safety
-            .items(&menu_options)
-            .interact()?;
-
-        match selection {
-            0 => {
-                // Do nothing - safe default (dialoguer already shows selection)
-            }
-            i if i <= self.last_suggested_commands.len() => {
-                // Execute suggested command (i-1 because index 0 is "Do nothing")
-                let command = &self.last_suggested_commands[i - 1];
-                self.execute_command_with_confirmation(command, false)
-                    .await?;
-            }
-            _ => {
-                // Custom command
-                use dialoguer::Input;
-                let custom_command: String = Input::new()
-                    .with_prompt("Enter your command")
-                    .interact_text()?;
+        // "Do nothing" unless the config opted into another entry
+        let ui = &self.config.ui;
+        let default = menu_default_index(ui.menu_default, self.last_suggested_commands.len());
+        let selection = select_for_terminal(&ui.menu_prompt, &menu_options, default)?;
+
+        Ok(match selection {
+            0 => MenuChoice::Skip,
+            i if i <= self.last_suggested_commands.len() => MenuChoice::Run(i - 1),
+            _ => MenuChoice::Custom,
+        })
+    }
 
-                if !custom_command.trim().is_empty() {
-                    self.execute_command(&custom_command).await?;
-                } else {
-                    println!("{}", style("No command entered.").fg(Color::Green));
+    /// `menu_style = "keys"`: one prompt line and a single key press, no menu redraw
+    fn pick_suggestion_by_key(&self) -> Result<MenuChoice> {
+        let count = self.last_suggested_commands.len();
+        let term = console::Term::stdout();
+        let range = if count == 1 { "1".to_string() } else { format!("1-{}", count) };
+        println!();
+        term.write_str(&format!(
+            "{} {} {} {} {} {} ",
+            style("Run").bold(),
+            style(range).fg(Color::Green),
+            style("·").dim(),
+            style("c custom").fg(Color::Green),
+            style("· 0/Esc skip").dim(),
+            style("›").fg(Color::Cyan)
+        ))?;
+
+        let choice = loop {
+            match term.read_key() {
+                Ok(key) => {
+                    if let Some(choice) = key_menu_choice(&key, count) {
+                        break choice;
+                    }
                 }
+                // Ctrl-C while waiting for the key backs out, like in the menu
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => break MenuChoice::Skip,
+                Err(e) => return Err(e.into()),
             }
-        }
+        };
 
-        Ok(())
+        let echo = match choice {
+            MenuChoice::Skip => style("skip".to_string()).dim(),
+            MenuChoice::Run(i) => style((i + 1).to_string()).fg(Color::Green),
+            MenuChoice::Custom => style("custom".to_string()).fg(Color::Green),
+        };
+        term.write_line(&echo.to_string())?;
+        Ok(choice)
     }
 
     fn looks_like_command(text: &str) -> bool {
@@ -981,34 +4129,203 @@ impl ShyRepl {
             .any(|pattern| regex::Regex::new(pattern).is_ok_and(|re| re.is_match(text)))
     }
 
-    async fn change_model(&mut self) -> Result<()> {
+    /// Send the same context to two models at once and print both answers
+    async fn compare_models(&mut self, first: &str, second: &str, question: &str) -> Result<()> {
+        let question = if question.trim().is_empty() {
+            match self.last_user_message.clone() {
+                Some(message) => message,
+                None => {
+                    println!(
+                        "{} Nothing to compare yet. Add a question after the model names.",
+                        style("⚠").fg(Color::Yellow)
+                    );
+                    return Ok(());
+                }
+            }
+        } else {
+            question.to_string()
+        };
+
+        let context = self.create_context(&question);
+        let system_prompt = self.system_prompt();
+        if self.dry_run {
+            return self.print_dry_run(&system_prompt, &context);
+        }
+
+        let clients = [
+            Self::build_client_for_model(&self.config, first),
+            Self::build_client_for_model(&self.config, second),
+        ];
+        println!(
+            "{}",
+            style(format!("Asking {} and {}...", first, second)).dim()
+        );
+
+        let (first_result, second_result) = tokio::join!(
+            timed(clients[0].complete(Some(&system_prompt), &context)),
+            timed(clients[1].complete(Some(&system_prompt), &context))
+        );
+
+        for (client, (result, elapsed)) in clients.iter().zip([first_result, second_result]) {
+            println!();
+            println!(
+                "{} {}",
+                style(format!("── {} ", client.model())).bold().fg(Color::Cyan),
+                style(format!("({:.1}s)", elapsed)).fg(Color::Yellow)
+            );
+            match result {
+                Ok(response) if response.trim().is_empty() => {
+                    println!("{}", style("(empty response)").dim());
+                }
+                Ok(response) => {
+                    client.print_with_syntax_highlighting(&response);
+                    println!();
+                }
+                Err(e) => {
+                    println!("{} {}", style("✗").fg(Color::Red), style(e).fg(Color::Red));
+                }
+            }
+        }
+        println!();
+
+        Ok(())
+    }
+
+    /// Print models matching `filter`, fetched live with the built-in list as fallback
+    async fn list_models(&self, filter: Option<&str>) {
+        const MAX_SHOWN: usize = 40;
+
+        let models = match self.client.list_models().await {
+            Ok(models) if !models.is_empty() => models,
+            Ok(_) | Err(_) => {
+                println!(
+                    "{}",
+                    style("Could not fetch models from OpenRouter; showing the built-in list").dim()
+                );
+                AVAILABLE_MODELS.iter().map(|m| m.id.to_string()).collect()
+            }
+        };
+
+        let matches = filter_models(models, filter.unwrap_or(""));
+        println!();
+        match filter {
+            Some(term) => println!(
+                "{} {}",
+                style(format!("Models matching \"{}\"", term)).bold().fg(Color::Cyan),
+                style(format!("({})", matches.len())).dim()
+            ),
+            None => println!(
+                "{} {}",
+                style("Available models").bold().fg(Color::Cyan),
+                style(format!("({})", matches.len())).dim()
+            ),
+        }
+
+        for model in matches.iter().take(MAX_SHOWN) {
+            if *model == self.config.default_model {
+                println!(
+                    "  {} {} {}",
+                    style("●").fg(Color::Green),
+                    style(model).fg(Color::Green),
+                    style("(active)").dim()
+                );
+            } else {
+                println!("    {}", model);
+            }
+        }
+        if matches.len() > MAX_SHOWN {
+            println!(
+                "  {}",
+                style(format!(
+                    "...and {} more; narrow it down with /models <filter>",
+                    matches.len() - MAX_SHOWN
+                ))
+                .dim()
+            );
+        }
+        println!("{}", style("Use /model to switch.").dim());
+        println!();
+    }
+
+    /// `None` when the user backs out with Esc or Ctrl-C
+    fn pick_model(&self, prompt: &str) -> Result<Option<String>> {
         use dialoguer::{theme::ColorfulTheme, Select};
 
         let current_index = AVAILABLE_MODELS
             .iter()
-            .position(|&model| model == self.config.default_model)
+            .position(|model| model.id == self.config.default_model)
             .unwrap_or(0);
 
         println!();
         let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Choose new default model")
+            .with_prompt(prompt)
             .default(current_index)
-            .items(AVAILABLE_MODELS)
-            .interact()?;
+            .items(&model_menu_items())
+            .interact_opt();
+
+        Ok(cancellable(selection)?
+            .flatten()
+            .map(|selection| AVAILABLE_MODELS[selection].id.to_string()))
+    }
+
+    /// The configured model was retired or mistyped: pick another, optionally save it,
+    /// and ask the same question again
+    async fn recover_from_missing_model(&mut self, message: &str, error: anyhow::Error) -> Result<String> {
+        if self.quiet || !console::Term::stdout().is_term() {
+            return Err(error);
+        }
+
+        println!(
+            "{} {}",
+            style("⚠").fg(Color::Yellow),
+            style(format!(
+                "Model {} is not available on OpenRouter.",
+                self.config.default_model
+            ))
+            .fg(Color::Yellow)
+        );
+        let Some(new_model) = self.pick_model("Choose a model to continue with")? else {
+            return Err(error);
+        };
+        let previous = std::mem::replace(&mut self.config.default_model, new_model);
+        self.rebuild_client();
+        self.load_context_limit().await;
+        println!(
+            "{} {}",
+            style("✓").fg(Color::Green),
+            setting_change("Model", &previous, &self.config.default_model)
+        );
+
+        let save = dialoguer::Confirm::new()
+            .with_prompt(format!("Save {} as your default model?", self.config.default_model))
+            .default(true)
+            .interact();
+        if cancellable(save)?.unwrap_or(false) {
+            self.config.save()?;
+        }
+        println!();
+
+        self.send_chat(message).await
+    }
 
-        let new_model = AVAILABLE_MODELS[selection].to_string();
+    async fn change_model(&mut self) -> Result<()> {
+        let Some(new_model) = self.pick_model("Choose new default model")? else {
+            println!("{} Model unchanged.", style("•").fg(Color::Cyan));
+            return Ok(());
+        };
 
         if new_model != self.config.default_model {
-            self.config.default_model = new_model.clone();
+            let previous = std::mem::replace(&mut self.config.default_model, new_model);
             self.config.save()?;
 
             // Update client with new model
-            self.client = OpenRouterClient::new(self.config.api_key.clone(), new_model.clone());
-            self.config.default_model = new_model;
+            self.rebuild_client();
+            self.load_context_limit().await;
 
             println!(
-                "{} Model changed successfully!",
-                style("✓").fg(Color::Green)
+                "{} {}",
+                style("✓").fg(Color::Green),
+                setting_change("Model", &previous, &self.config.default_model)
             );
         } else {
             println!("{} Model unchanged.", style("•").fg(Color::Cyan));
@@ -1021,13 +4338,14 @@ impl ShyRepl {
     async fn show_bash_history_interactive(&mut self) -> Result<()> {
         let mut current_offset = self.history_offset;
         let page_size = 20;
+        let mut search: Option<String> = None;
 
         loop {
             // Get paginated history
             let (commands, source_info, total_count) =
-                self.get_paginated_history(current_offset, page_size)?;
+                self.get_paginated_history(current_offset, page_size, search.as_deref())?;
 
-            if commands.is_empty() && current_offset == 0 {
+            if commands.is_empty() && current_offset == 0 && search.is_none() {
                 println!();
                 println!("{}", style("No shell history found").fg(Color::Yellow));
                 println!("{}", style("History may be empty or not accessible").dim());
@@ -1043,22 +4361,36 @@ impl ShyRepl {
                 style("Source").fg(Color::Green),
                 style(&source_info).fg(Color::White)
             );
-            println!(
-                "  {}: {}",
-                style("Total commands").fg(Color::Green),
-                style(total_count).fg(Color::White)
-            );
+            match &search {
+                Some(term) => println!(
+                    "  {}: \"{}\" ({} matches)",
+                    style("Search").fg(Color::Green),
+                    style(term).fg(Color::White),
+                    style(total_count).fg(Color::White)
+                ),
+                None => println!(
+                    "  {}: {}",
+                    style("Total commands").fg(Color::Green),
+                    style(total_count).fg(Color::White)
+                ),
+            }
 
             let start_num = current_offset + 1;
             let end_num = (current_offset + commands.len()).min(total_count);
-            println!(
-                "  {}: {}-{}",
-                style("Showing").fg(Color::Green),
-                style(start_num).fg(Color::White),
-                style(end_num).fg(Color::White)
-            );
+            if !commands.is_empty() {
+                println!(
+                    "  {}: {}-{}",
+                    style("Showing").fg(Color::Green),
+                    style(start_num).fg(Color::White),
+                    style(end_num).fg(Color::White)
+                );
+            }
             println!();
 
+            if commands.is_empty() {
+                println!("{}", style("No commands match this search").fg(Color::Yellow));
+            }
+
             for (i, cmd) in commands.iter().enumerate() {
                 let formatted_cmd = self.format_command_with_syntax(cmd);
                 println!(
@@ -1082,13 +4414,18 @@ impl ShyRepl {
                 menu_options.push("Next 20 →".to_string());
             }
 
+            if !commands.is_empty() {
+                menu_options.push("Run a command".to_string());
+                menu_options.push("Ask AI about a command".to_string());
+            }
+            menu_options.push("Search history".to_string());
+            if search.is_some() {
+                menu_options.push("Clear search".to_string());
+            }
             menu_options.push("Change history source".to_string());
 
-            let selection = Select::with_theme(&ColorfulTheme::default())
-                .with_prompt("Navigation")
-                .default(0)
-                .items(&menu_options)
-                .interact()?;
+            // Sized afresh on every page, in case the window changed since the last one
+            let selection = select_for_terminal("Navigation", &menu_options, 0)?;
 
             match menu_options[selection].as_str() {
                 "Exit history" => break,
@@ -1098,25 +4435,82 @@ impl ShyRepl {
                 "Next 20 →" => {
                     current_offset += page_size;
                 }
-                "Change history source" => {
-                    if self.select_history_source().await? {
-                        // Reset offset when switching sources
-                        current_offset = 0;
+                "Run a command" => {
+                    if let Some(command) = self.pick_history_command(&commands, start_num)? {
+                        self.execute_command(&command).await?;
+                        println!();
+                    }
+                }
+                "Ask AI about a command" => {
+                    if let Some(command) = self.pick_history_command(&commands, start_num)? {
+                        let actions = ["Explain what it does", "Suggest an improvement"];
+                        let action = Select::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Ask the AI to")
+                            .default(0)
+                            .items(&actions)
+                            .interact_opt();
+                        let Some(action) = cancellable(action)?.flatten() else {
+                            continue;
+                        };
+                        let message = history_question(&command, action == 1);
+                        if search.is_none() {
+                            self.history_offset = current_offset;
+                        }
+                        return self.handle_chat(&message).await;
                     }
                 }
+                "Search history" => {
+                    let term = dialoguer::Input::<String>::new()
+                        .with_prompt("Search for")
+                        .with_initial_text(search.clone().unwrap_or_default())
+                        .allow_empty(true)
+                        .interact_text();
+                    let Some(term) = cancellable(term)? else {
+                        continue;
+                    };
+                    let term = term.trim();
+                    search = (!term.is_empty()).then(|| term.to_string());
+                    current_offset = 0;
+                }
+                "Clear search" => {
+                    search = None;
+                    current_offset = 0;
+                }
+                "Change history source" if self.select_history_source().await? => {
+                    // Reset offset when switching sources
+                    current_offset = 0;
+                }
                 _ => {}
             }
         }
 
-        // Update stored offset
-        self.history_offset = current_offset;
+        // Update stored offset (offsets into a filtered list don't carry over)
+        if search.is_none() {
+            self.history_offset = current_offset;
+        }
         Ok(())
     }
 
+    /// Let the user pick one of the commands on the current history page
+    fn pick_history_command(&self, commands: &[String], start_num: usize) -> Result<Option<String>> {
+        let mut items = vec!["Cancel".to_string()];
+        items.extend(
+            commands
+                .iter()
+                .enumerate()
+                .map(|(i, cmd)| format!("{:2}. {}", start_num + i, cmd)),
+        );
+
+        let selection = select_for_terminal("Which command?", &items, 0)?;
+
+        Ok(selection.checked_sub(1).map(|i| commands[i].clone()))
+    }
+
     fn get_paginated_history(
         &self,
         offset: usize,
         limit: usize,
+        search: Option<&str>,
     ) -> Result<(Vec<String>, String, usize)> {
         let history_paths = self.get_shell_history_paths();
 
@@ -1125,7 +4519,10 @@ impl ShyRepl {
                 continue;
             };
 
-            let all_commands = self.parse_history_by_type(&contents, shell_type);
+            let mut all_commands = self.parse_history_by_type(&contents, shell_type);
+            if let Some(term) = search {
+                all_commands = filter_history(all_commands, term);
+            }
             let total_count = all_commands.len();
             
             let commands: Vec<String> = all_commands
@@ -1143,7 +4540,7 @@ impl ShyRepl {
     }
 
     async fn select_history_source(&mut self) -> Result<bool> {
-        let all_paths = self.collect_all_history_paths();
+        let all_paths = Self::collect_all_history_paths();
         let (available_sources, available_indices) = self.build_available_sources(&all_paths);
 
         if available_sources.is_empty() {
@@ -1156,13 +4553,16 @@ impl ShyRepl {
             return Ok(false);
         }
 
-        let selection = self.prompt_source_selection(&available_sources)?;
-        self.handle_source_selection(selection, &available_sources, &available_indices);
+        let Some(selection) = self.prompt_source_selection(&available_sources)? else {
+            return Ok(false);
+        };
+        self.handle_source_selection(selection, &available_sources, &available_indices, &all_paths)?;
 
         Ok(true) // Source was changed
     }
 
-    fn collect_all_history_paths(&self) -> Vec<(PathBuf, &'static str)> {
+    /// Every known history file location, whether or not it exists
+    pub(crate) fn collect_all_history_paths() -> Vec<(PathBuf, &'static str)> {
         let mut all_paths = Vec::new();
 
         if let Ok(histfile) = env::var("HISTFILE") {
@@ -1187,6 +4587,16 @@ impl ShyRepl {
             }
         }
 
+        // PSReadLine history lives under AppData on Windows and ~/.local/share elsewhere
+        let powershell_history = if cfg!(target_os = "windows") {
+            dirs::data_dir().map(|dir| dir.join("Microsoft/Windows/PowerShell/PSReadLine"))
+        } else {
+            dirs::home_dir().map(|home| home.join(".local/share/powershell/PSReadLine"))
+        };
+        if let Some(dir) = powershell_history {
+            all_paths.push((dir.join("ConsoleHost_history.txt"), "PowerShell"));
+        }
+
         all_paths
     }
 
@@ -1215,7 +4625,7 @@ impl ShyRepl {
             .and_then(|metadata| metadata.modified())
             .and_then(|modified| {
                 modified.duration_since(std::time::UNIX_EPOCH)
-                    .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Invalid timestamp"))
+                    .map_err(|_| std::io::Error::other("Invalid timestamp"))
             }) {
             Ok(duration) => self.format_file_timestamp(duration.as_secs() as i64),
             Err(_) => "unknown".to_string(),
@@ -1235,30 +4645,38 @@ impl ShyRepl {
         println!();
     }
 
-    fn prompt_source_selection(&self, available_sources: &[String]) -> Result<usize> {
+    /// `None` when the user backs out with Esc or Ctrl-C
+    fn prompt_source_selection(&self, available_sources: &[String]) -> Result<Option<usize>> {
         use dialoguer::{theme::ColorfulTheme, Select};
 
         let mut menu_options = vec!["Auto-detect (default behavior)".to_string()];
         menu_options.extend(available_sources.iter().cloned());
 
         println!();
-        Select::with_theme(&ColorfulTheme::default())
+        let selection = Select::with_theme(&ColorfulTheme::default())
             .with_prompt("Select history source")
             .default(0)
             .items(&menu_options)
-            .interact()
-            .map_err(Into::into)
+            .interact_opt();
+        Ok(cancellable(selection)?.flatten())
     }
 
-    fn handle_source_selection(&mut self, selection: usize, available_sources: &[String], available_indices: &[usize]) {
+    /// Remember the chosen source in the config so it survives restarts
+    fn handle_source_selection(
+        &mut self,
+        selection: usize,
+        available_sources: &[String],
+        available_indices: &[usize],
+        all_paths: &[(PathBuf, &str)],
+    ) -> Result<()> {
         if selection == 0 {
-            self.selected_history_source = None;
+            self.config.history_file = None;
             println!();
             println!("{}", style("Reset to auto-detection").fg(Color::Green));
             println!();
         } else {
-            let source_index = available_indices[selection - 1];
-            self.selected_history_source = Some(source_index);
+            let (path, _) = &all_paths[available_indices[selection - 1]];
+            self.config.history_file = Some(path.clone());
             println!();
             println!(
                 "{} {}",
@@ -1267,6 +4685,9 @@ impl ShyRepl {
             );
             println!();
         }
+        // The AI context should see the new history straight away
+        *self.context_cache.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        self.config.save()
     }
 
     fn format_file_timestamp(&self, timestamp: i64) -> String {
@@ -1329,7 +4750,7 @@ impl ShyRepl {
 
         for (path, shell_type) in history_paths {
             if path.exists() {
-                if let Ok(contents) = fs::read_to_string(&path) {
+                if let Ok((contents, _)) = read_history_lossy(&path) {
                     let commands = if shell_type == "Fish" {
                         self.parse_fish_history(&contents)
                     } else {
@@ -1357,20 +4778,67 @@ impl ShyRepl {
         if !path.exists() {
             return Ok(None);
         }
-        
-        match fs::read_to_string(path) {
-            Ok(contents) => Ok(Some(contents)),
-            Err(_) => Ok(None),
+
+        let Ok((contents, lossy)) = read_history_lossy(path) else {
+            return Ok(None);
+        };
+
+        // Warn once per file; history is re-read for every request
+        if lossy {
+            let mut warned = self.warned_history_files.lock().unwrap_or_else(|e| e.into_inner());
+            if warned.insert(path.to_path_buf()) {
+                eprintln!(
+                    "{}",
+                    style(format!(
+                        "⚠ {} contains invalid UTF-8; unreadable bytes were replaced",
+                        path.display()
+                    ))
+                    .dim()
+                );
+            }
         }
+
+        Ok(Some(contents))
     }
 
     fn parse_history_by_type(&self, contents: &str, shell_type: &str) -> Vec<String> {
         match shell_type {
             "Fish" => self.parse_fish_history(contents),
+            "PowerShell" => self.parse_powershell_history(contents),
             _ => self.parse_standard_history(contents),
         }
     }
 
+    pub fn parse_powershell_history(&self, contents: &str) -> Vec<String> {
+        let mut commands = Vec::new();
+        let mut current_command = String::new();
+
+        for line in contents.lines() {
+            // PSReadLine continues multi-line commands with a trailing backtick
+            if let Some(continued) = line.strip_suffix('`') {
+                current_command.push_str(continued);
+                current_command.push('\n');
+                continue;
+            }
+
+            current_command.push_str(line);
+            let command = current_command.trim().to_string();
+            if !command.is_empty() {
+                commands.push(command);
+            }
+            current_command.clear();
+        }
+
+        if !current_command.trim().is_empty() {
+            commands.push(current_command.trim().to_string());
+        }
+
+        commands
+            .into_iter()
+            .filter(|cmd| cmd.len() < 200)
+            .collect()
+    }
+
     pub fn parse_fish_history(&self, contents: &str) -> Vec<String> {
         let mut commands = Vec::new();
         let mut current_command = String::new();
@@ -1411,133 +4879,64 @@ impl ShyRepl {
             .collect()
     }
 
-    fn get_shell_history_paths(&self) -> Vec<(PathBuf, &'static str)> {
-        let mut all_paths = Vec::new();
-
-        // Build list of all available paths
-        if let Ok(histfile) = env::var("HISTFILE") {
-            all_paths.push((PathBuf::from(histfile), "Custom"));
-        }
-
-        if let Ok(home) = env::var("HOME") {
-            let home_path = PathBuf::from(home);
-
-            let all_files = [
-                (".local/share/fish/fish_history", "Fish"),
-                (".zsh_history", "Zsh"),
-                (".bash_history", "Bash"),
-                (".history", "Shell"),
-                (".sh_history", "Shell"),
-            ];
-
-            for (file, shell_type) in &all_files {
-                let path = home_path.join(file);
-                if !all_paths.iter().any(|(p, _)| p == &path) {
-                    all_paths.push((path, *shell_type));
-                }
-            }
-        }
-
-        // If a specific source is selected, return only that one
-        if let Some(selected_index) = self.selected_history_source {
-            if selected_index < all_paths.len() {
-                return vec![all_paths[selected_index].clone()];
-            }
-        }
-
-        // Otherwise, return prioritized list
-        let mut paths = Vec::new();
-
-        // Detect current shell and prioritize its history
-        let current_shell = self.detect_current_shell();
-
-        // Add current shell's history first if no manual selection
-        for (path, shell_type) in &all_paths {
-            match current_shell.as_str() {
-                "fish" if *shell_type == "Fish" => {
-                    paths.push((path.clone(), *shell_type));
-                    break;
-                }
-                "zsh" if *shell_type == "Zsh" => {
-                    paths.push((path.clone(), *shell_type));
-                    break;
-                }
-                "bash" if *shell_type == "Bash" => {
-                    paths.push((path.clone(), *shell_type));
-                    break;
-                }
-                _ => {}
-            }
-        }
-
-        // Add remaining paths as fallbacks
-        for (path, shell_type) in all_paths {
-            if !paths.iter().any(|(p, _)| p == &path) {
-                paths.push((path, shell_type));
-            }
-        }
+    pub(crate) fn get_shell_history_paths(&self) -> Vec<(PathBuf, &'static str)> {
+        history_search_order(
+            Self::collect_all_history_paths(),
+            self.config.history_file.as_deref(),
+            &Self::shell_type(&self.config),
+        )
+    }
 
-        paths
+    /// `shell_type` from the config, otherwise the detected shell
+    pub(crate) fn shell_type(config: &Config) -> String {
+        config
+            .shell_type
+            .clone()
+            .unwrap_or_else(Self::detect_current_shell)
     }
 
-    fn detect_current_shell(&self) -> String {
-        // Check if fish is running by looking at parent processes
-        if let Ok(output) = std::process::Command::new("pgrep")
-            .args(["-f", "fish"])
-            .output()
-        {
-            if !output.stdout.is_empty() {
-                return "fish".to_string();
-            }
-        }
-
-        // Check if the parent process is fish
-        if let Ok(ppid) = env::var("PPID") {
-            if let Ok(output) = std::process::Command::new("ps")
-                .args(["-p", &ppid, "-o", "comm="])
-                .output()
-            {
-                let parent_process = String::from_utf8_lossy(&output.stdout)
-                    .trim()
-                    .to_lowercase();
-                if parent_process.contains("fish") {
-                    return "fish".to_string();
-                } else if parent_process.contains("zsh") {
-                    return "zsh".to_string();
-                } else if parent_process.contains("bash") {
-                    return "bash".to_string();
-                }
-            }
-        }
+    /// The shell shy was started from. Detected once per run, reading the parent
+    /// process from `/proc` where possible; `ps` is only spawned when it is installed
+    pub(crate) fn detect_current_shell() -> String {
+        static DETECTED: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        DETECTED
+            .get_or_init(|| {
+                let shell_var = env::var("SHELL").ok();
+                let module_path = env::var("PSModulePath").ok();
+                Self::parent_process_name()
+                    .as_deref()
+                    .and_then(shell_from_process_name)
+                    .or_else(|| {
+                        powershell_from_env(
+                            module_path.as_deref(),
+                            shell_var.as_deref(),
+                            cfg!(target_os = "windows"),
+                        )
+                    })
+                    .or_else(|| shell_var.as_deref().and_then(shell_from_process_name))
+                    .unwrap_or("unknown")
+                    .to_string()
+            })
+            .clone()
+    }
 
-        // Check what shell is currently running
-        if let Ok(output) = std::process::Command::new("ps")
-            .args(["-p", &std::process::id().to_string(), "-o", "comm="])
-            .output()
+    /// Name of the process that started shy, if it can be found
+    fn parent_process_name() -> Option<String> {
+        #[cfg(unix)]
         {
-            let shell_process = String::from_utf8_lossy(&output.stdout)
-                .trim()
-                .to_lowercase();
-            if shell_process.contains("fish") {
-                return "fish".to_string();
-            } else if shell_process.contains("zsh") {
-                return "zsh".to_string();
-            } else if shell_process.contains("bash") {
-                return "bash".to_string();
+            let ppid = std::os::unix::process::parent_id().to_string();
+            if let Ok(name) = fs::read_to_string(format!("/proc/{}/comm", ppid)) {
+                return Some(name.trim().to_string());
             }
-        }
-
-        // Fallback: check SHELL environment variable
-        if let Ok(shell) = env::var("SHELL") {
-            if shell.contains("fish") {
-                return "fish".to_string();
-            } else if shell.contains("zsh") {
-                return "zsh".to_string();
-            } else if shell.contains("bash") {
-                return "bash".to_string();
+            if is_installed("ps") {
+                let output = std::process::Command::new("ps")
+                    .args(["-p", &ppid, "-o", "comm="])
+                    .output()
+                    .ok()
+                    .filter(|output| output.status.success())?;
+                return Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
             }
         }
-
-        "unknown".to_string()
+        None
     }
 }
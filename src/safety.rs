@@ -0,0 +1,103 @@
+use regex::Regex;
+
+use crate::shell::shell_words;
+
+/// Patterns for commands that can destroy data or the system, with the reason shown
+const DANGEROUS_PATTERNS: &[(&str, &str)] = &[
+    (r"\brm\s+(-\S+\s+)*(/|~/?|\*|\$HOME/?)(\s|$)", "deletes a root, home or wildcard path"),
+    (r"\brm\s+-[a-zA-Z]*([rR][a-zA-Z]*[fF]|[fF][a-zA-Z]*[rR])", "recursively force-deletes files"),
+    (r"(^|[;&|]\s*|sudo\s+)mkfs(\.\w+)?\b", "formats a filesystem"),
+    (r"\bdd\b.*\bof=/dev/", "writes directly to a device"),
+    (r">\s*/dev/(sd|nvme|disk|hd)", "overwrites a disk device"),
+    (r"(^|[;&|]\s*|sudo\s+)(shutdown|reboot|halt|poweroff)\b", "shuts down or restarts the machine"),
+    (r"\bchmod\s+(-[a-zA-Z]*R[a-zA-Z]*\s+)?[0-7]*777\s+/", "makes system files world-writable"),
+    (r":\(\)\s*\{\s*:\|:&\s*\};:", "is a fork bomb"),
+    (r"\bgit\s+push\b.*(\s--force\b|\s-f\b)", "force-pushes over remote history"),
+    (r"\bgit\s+(reset\s+--hard|clean\s+-[a-zA-Z]*f)", "discards uncommitted work"),
+    (r"\b(curl|wget)\b.*\|\s*(sudo\s+)?(ba|z)?sh\b", "runs a downloaded script"),
+];
+
+/// Why `command` is too risky to run without an explicit confirmation, if it is
+pub fn dangerous_reason(command: &str) -> Option<&'static str> {
+    DANGEROUS_PATTERNS.iter().find_map(|(pattern, reason)| {
+        Regex::new(pattern)
+            .is_ok_and(|re| re.is_match(command))
+            .then_some(*reason)
+    })
+}
+
+/// Patterns for commands that act with elevated privileges or on system state
+const PRIVILEGED_PATTERNS: &[(&str, &str)] = &[
+    (r"(^|[;&|(]\s*)(sudo|doas|pkexec)\b", "runs with root privileges"),
+    (r"(^|[;&|(]\s*)su(\s|$)", "switches to another user"),
+    (
+        r"\b(systemctl|service|launchctl)\s+(\S+\s+)*(start|stop|restart|reload|enable|disable|mask|load|unload)\b",
+        "changes system services",
+    ),
+    (r"(^|[\s=<>'])/(etc|usr|boot|sbin|bin|lib|opt|System|Library)(/|\s|$)", "touches system files"),
+];
+
+/// Why `command` needs elevated privileges or changes the system, if it does.
+/// Lighter than `dangerous_reason`: a heads-up, not a forced confirmation
+pub fn privilege_reason(command: &str) -> Option<&'static str> {
+    PRIVILEGED_PATTERNS.iter().find_map(|(pattern, reason)| {
+        Regex::new(pattern)
+            .is_ok_and(|re| re.is_match(command))
+            .then_some(*reason)
+    })
+}
+
+/// `git branch` options that create, delete, rename or retarget branches
+const BRANCH_WRITING_OPTIONS: &[&str] = &[
+    "delete", "move", "copy", "force", "track", "no-track", "set-upstream-to", "unset-upstream",
+    "edit-description", "create-reflog",
+];
+
+/// Whether one pipeline stage writes despite starting with an allowlisted command:
+/// `git diff --output=FILE`, `git branch -D main`, `git branch new-name`
+fn writes_despite_allowlist(words: &[String]) -> bool {
+    let writes_file = words
+        .iter()
+        .any(|word| word == "-o" || word == "--output" || word.starts_with("--output="));
+    let is_git_branch = words.len() > 1 && words[0] == "git" && words[1] == "branch";
+    writes_file || (is_git_branch && changes_branches(&words[2..]))
+}
+
+/// Anything but listing: short flags other than `-a`, `-r`, `-v`, `-l`, `-q`, the
+/// writing long options, or a branch name outside `--list`
+fn changes_branches(args: &[String]) -> bool {
+    let listing = args.iter().any(|arg| {
+        arg == "--list" || (!arg.starts_with("--") && arg.starts_with('-') && arg.contains('l'))
+    });
+    args.iter().any(|arg| match arg.strip_prefix("--") {
+        Some(long) => BRANCH_WRITING_OPTIONS.contains(&long.split('=').next().unwrap_or(long)),
+        None => match arg.strip_prefix('-') {
+            Some(short) => !short.chars().all(|flag| "arvlq".contains(flag)),
+            None => !listing,
+        },
+    })
+}
+
+/// Whether `command` may run without confirmation under `auto_run_safe`: every
+/// stage of a pipeline must start with an allowlisted command (`ls`, `git status`),
+/// and nothing may chain, redirect or substitute another command, or write through
+/// an option such as `--output`
+pub fn is_allowlisted(command: &str, allowlist: &[String]) -> bool {
+    let command = command.trim();
+    if command.is_empty()
+        || dangerous_reason(command).is_some()
+        || command.contains(['>', '<', ';', '&', '`', '\n'])
+        || command.contains("$(")
+    {
+        return false;
+    }
+
+    command.split('|').all(|stage| {
+        let words = shell_words(stage);
+        !writes_despite_allowlist(&words)
+            && allowlist.iter().any(|allowed| {
+                let allowed = shell_words(allowed);
+                !allowed.is_empty() && words.starts_with(&allowed)
+            })
+    })
+}
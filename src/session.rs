@@ -0,0 +1,133 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+/// One question and the answer it got
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Turn {
+    pub question: String,
+    pub answer: String,
+    /// The model that answered, which may be a fallback of the session's model.
+    /// Empty in sessions saved before it was recorded
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub model: String,
+}
+
+/// The conversation so far in a REPL session
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub model: String,
+    pub turns: Vec<Turn>,
+    /// Turns added since the last save
+    #[serde(skip)]
+    unsaved: usize,
+}
+
+impl Session {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, question: &str, answer: &str, model: &str) {
+        self.turns.push(Turn {
+            question: question.to_string(),
+            answer: answer.to_string(),
+            model: model.to_string(),
+        });
+        self.unsaved += 1;
+    }
+
+    /// Add the rest of a cut-off answer to the latest turn
+    pub fn extend_last_answer(&mut self, continuation: &str) {
+        if let Some(turn) = self.turns.last_mut() {
+            turn.answer.push_str(continuation);
+            self.unsaved += 1;
+        }
+    }
+
+    pub fn has_unsaved_turns(&self) -> bool {
+        self.unsaved > 0
+    }
+
+    /// Directory where `/save` puts sessions when no path is given
+    pub fn sessions_dir() -> Result<PathBuf> {
+        let mut path = Config::config_dir()?;
+        path.push("sessions");
+        Ok(path)
+    }
+
+    /// Timestamped file name in `sessions_dir`
+    pub fn default_path() -> Result<PathBuf> {
+        let name = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+        Ok(Self::sessions_dir()?.join(format!("{}.json", name)))
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    /// The conversation as Markdown notes: metadata first, then each question as a
+    /// heading with its answer below, code blocks kept as they were
+    pub fn to_markdown(&self, exported_at: &str) -> String {
+        let mut markdown = String::from("# shy session\n\n");
+        markdown.push_str(&format!("- **Model:** {}\n", self.model));
+        markdown.push_str(&format!("- **Exported:** {}\n", exported_at));
+        markdown.push_str(&format!("- **Questions:** {}\n", self.turns.len()));
+
+        for turn in &self.turns {
+            let question = turn.question.trim();
+            let mut lines = question.lines();
+            let heading = lines.next().unwrap_or_default();
+            markdown.push_str(&format!("\n## {}\n\n", heading.trim_start_matches('#').trim()));
+            // Headings are one line, so the rest of a long question is quoted below
+            let rest: Vec<&str> = lines.collect();
+            if !rest.is_empty() {
+                for line in rest {
+                    if line.trim().is_empty() {
+                        markdown.push_str(">\n");
+                    } else {
+                        markdown.push_str(&format!("> {}\n", line));
+                    }
+                }
+                markdown.push('\n');
+            }
+
+            let answer = turn.answer.trim();
+            markdown.push_str(answer);
+            markdown.push('\n');
+            // A cut-off answer can leave a fence open, swallowing the next heading
+            let fences = answer
+                .lines()
+                .filter(|line| line.trim_start().starts_with("```"))
+                .count();
+            if fences % 2 == 1 {
+                markdown.push_str("```\n");
+            }
+        }
+        markdown
+    }
+
+    pub fn export_markdown(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_markdown(&chrono::Local::now().to_rfc3339()))?;
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::api::OpenRouterClient;
+use crate::cache::AnswerCache;
+use crate::config::Config;
+
+/// Last copy of the `prompt_url` template, reused until it is `prompt_url_ttl_secs` old
+pub fn cache_path(url: &str) -> Result<PathBuf> {
+    Ok(cache_file(&Config::config_dir()?.join("cache"), url))
+}
+
+/// One file per URL in `dir`, so changing `prompt_url` (or switching profiles) never
+/// serves the template downloaded from another one
+pub fn cache_file(dir: &Path, url: &str) -> PathBuf {
+    dir.join(format!("prompt-{}.md", AnswerCache::key(&serde_json::json!(url))))
+}
+
+/// The team template from `url`: the cached copy while it is younger than `ttl`, otherwise
+/// a fresh download. When the download fails the stale copy is better than nothing; `None`
+/// means there is no template to use and the built-in instructions apply
+pub async fn load(client: &OpenRouterClient, url: &str, ttl: Duration, cache: &Path) -> Option<String> {
+    let cached = read_cache(cache);
+    if let Some((template, age)) = &cached {
+        if *age < ttl {
+            return Some(template.clone());
+        }
+    }
+
+    match client.fetch_text(url).await {
+        Ok(template) if !template.trim().is_empty() => {
+            let template = template.trim().to_string();
+            if let Some(parent) = cache.parent() {
+                fs::create_dir_all(parent).ok();
+            }
+            if let Err(e) = fs::write(cache, &template) {
+                log::warn!("Could not cache the prompt from {}: {}", url, e);
+            }
+            Some(template)
+        }
+        Ok(_) => {
+            log::warn!("prompt_url {} returned an empty template", url);
+            cached.map(|(template, _)| template)
+        }
+        Err(e) => {
+            log::warn!("Could not fetch prompt_url {}: {}", url, e);
+            cached.map(|(template, _)| template)
+        }
+    }
+}
+
+fn read_cache(path: &Path) -> Option<(String, Duration)> {
+    let template = fs::read_to_string(path).ok()?;
+    if template.trim().is_empty() {
+        return None;
+    }
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+    Some((template, age))
+}
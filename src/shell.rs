@@ -0,0 +1,355 @@
+use clap_complete::Shell;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Interpreters that `--shell` and the `shell` config key accept
+pub const SUPPORTED_SHELLS: &[&str] = &["sh", "bash", "zsh", "fish", "powershell"];
+
+/// Build the process that runs `command` in the user's shell. POSIX shells and fish
+/// all take the script via `-c`; anything unrecognised falls back to `cmd /C` on
+/// Windows and `sh -c` elsewhere
+pub fn shell_command(shell: &str, command: &str) -> Command {
+    match shell {
+        "powershell" => {
+            let program = if cfg!(target_os = "windows") {
+                "powershell"
+            } else {
+                "pwsh"
+            };
+            let mut process = Command::new(program);
+            process.args(["-NoProfile", "-Command", command]);
+            process
+        }
+        "sh" | "bash" | "zsh" | "fish" => {
+            let mut process = Command::new(shell);
+            process.arg("-c").arg(command);
+            process
+        }
+        _ if cfg!(target_os = "windows") => {
+            let mut process = Command::new("cmd");
+            process.args(["/C", command]);
+            process
+        }
+        _ => {
+            let mut process = Command::new("sh");
+            process.arg("-c").arg(command);
+            process
+        }
+    }
+}
+
+/// Where `shy completions --install` writes the script so the shell picks it up without
+/// extra setup: fish's and bash-completion's per-user directories, and `~/.zfunc` for zsh
+/// (which still needs to be on `fpath`). `None` for shells with no such convention
+pub fn completion_install_path(
+    shell: Shell,
+    home: &Path,
+    xdg_config_home: Option<&Path>,
+    xdg_data_home: Option<&Path>,
+) -> Option<PathBuf> {
+    let config_home = xdg_config_home.map_or_else(|| home.join(".config"), Path::to_path_buf);
+    let data_home = xdg_data_home.map_or_else(|| home.join(".local").join("share"), Path::to_path_buf);
+    match shell {
+        Shell::Fish => Some(config_home.join("fish").join("completions").join("shy.fish")),
+        Shell::Bash => Some(data_home.join("bash-completion").join("completions").join("shy")),
+        Shell::Zsh => Some(home.join(".zfunc").join("_shy")),
+        _ => None,
+    }
+}
+
+/// How one part of a chained command connects to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    /// `&&`: the next part runs only if this one succeeds
+    And,
+    /// `||`: the next part runs only if this one fails
+    Or,
+    /// `;`: the next part runs regardless
+    Then,
+}
+
+/// Split `cmd1 && cmd2 || cmd3` into its parts, each with the operator that follows
+/// it. Operators inside quotes are ignored and pipes stay within a part.
+pub fn split_chain(command: &str) -> Vec<(String, Option<Chain>)> {
+    let chars: Vec<char> = command.chars().collect();
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+        let operator = match (ch, next) {
+            _ if in_single || in_double => None,
+            ('&', Some('&')) => Some((Chain::And, 2)),
+            ('|', Some('|')) => Some((Chain::Or, 2)),
+            (';', _) => Some((Chain::Then, 1)),
+            _ => None,
+        };
+
+        if let Some((chain, width)) = operator {
+            let part = current.trim();
+            if !part.is_empty() {
+                parts.push((part.to_string(), Some(chain)));
+            }
+            current.clear();
+            i += width;
+            continue;
+        }
+
+        match ch {
+            '\\' if !in_single => {
+                current.push(ch);
+                if let Some(escaped) = next {
+                    current.push(escaped);
+                }
+                i += 2;
+                continue;
+            }
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            _ => {}
+        }
+        current.push(ch);
+        i += 1;
+    }
+
+    let last = current.trim();
+    if !last.is_empty() {
+        parts.push((last.to_string(), None));
+    } else if let Some(last) = parts.last_mut() {
+        // A trailing `;` doesn't chain into anything
+        last.1 = None;
+    }
+    parts
+}
+
+/// Expand only `~`, for `cmd /C` on Windows, which doesn't know it. Safe to run, since
+/// no variable's value ends up in the command text
+pub fn expand_home(command: &str) -> String {
+    expand_command(command, |_| None)
+}
+
+/// Whether `shell_command` runs this shell's commands through `cmd /C`
+pub fn runs_in_cmd(shell: &str) -> bool {
+    cfg!(target_os = "windows") && !matches!(shell, "powershell" | "sh" | "bash" | "zsh" | "fish")
+}
+
+/// Expand `~` and `$VAR` / `${VAR}` the way a POSIX shell would, for showing what a
+/// command refers to. Values are spliced in unquoted, so the result is for display only
+/// and never run. Single-quoted text is left alone, `~` is only expanded at the start of
+/// a word, and variables `lookup` doesn't know, `$1`, `$?` and `$(...)` are kept as written.
+pub fn expand_command(command: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let home = dirs::home_dir().map(|h| h.to_string_lossy().into_owned());
+    let chars: Vec<char> = command.chars().collect();
+    let mut result = String::with_capacity(command.len());
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch {
+            '\\' if !in_single && i + 1 < chars.len() => {
+                result.push(ch);
+                result.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '~' if !in_single && !in_double && at_word_start(&chars, i) => {
+                let next = chars.get(i + 1);
+                if let (Some(home), None | Some('/') | Some(' ')) = (&home, next) {
+                    if home.contains(' ') {
+                        result.push_str(&format!("\"{}\"", home));
+                    } else {
+                        result.push_str(home);
+                    }
+                    i += 1;
+                    continue;
+                }
+            }
+            '$' if !in_single => {
+                if let Some((value, consumed)) = expand_variable(&chars[i + 1..], &lookup) {
+                    result.push_str(&value);
+                    i += 1 + consumed;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        result.push(ch);
+        i += 1;
+    }
+
+    result
+}
+
+fn at_word_start(chars: &[char], i: usize) -> bool {
+    i == 0 || matches!(chars[i - 1], ' ' | '\t' | '=' | ':' | ';' | '|' | '&' | '(')
+}
+
+/// Parse `NAME` or `{NAME}` after a `$`, returning the value and chars consumed
+fn expand_variable(
+    rest: &[char],
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Option<(String, usize)> {
+    let (name, consumed) = if rest.first() == Some(&'{') {
+        let end = rest.iter().position(|&c| c == '}')?;
+        (rest[1..end].iter().collect::<String>(), end + 1)
+    } else {
+        let name: String = rest
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+            .collect();
+        let len = name.chars().count();
+        (name, len)
+    };
+
+    // `$_` is special in sh and PowerShell alike
+    let valid = !name.is_empty()
+        && name != "_"
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return None;
+    }
+
+    lookup(&name).map(|value| (value, consumed))
+}
+
+/// Split a command line into words on unquoted whitespace, keeping quotes and
+/// escapes as written so `grep "foo bar" file` gives `grep`, `"foo bar"`, `file`
+pub fn shell_words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut chars = command.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if !in_single => {
+                current.push(ch);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+                continue;
+            }
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            c if c.is_whitespace() && !in_single && !in_double => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        current.push(ch);
+    }
+
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// Rewrite bash-isms that fish rejects: `export A=b`, bare `A=b`, `A=b cmd`, and
+/// `&&` / `||` (spelled `; and` / `; or` so older fish versions accept them too).
+/// Returns `None` when nothing needs changing or the command is too complex to
+/// rewrite safely (subshells, command substitution)
+pub fn translate_for_fish(command: &str) -> Option<String> {
+    if command.contains(['(', '`']) {
+        return None;
+    }
+
+    let mut changed = false;
+    let mut translated = String::new();
+    for (part, chain) in split_chain(command) {
+        match translate_fish_part(&part) {
+            Some(rewritten) => {
+                changed = true;
+                translated.push_str(&rewritten);
+            }
+            None => translated.push_str(&part),
+        }
+        translated.push_str(match chain {
+            Some(Chain::And) => "; and ",
+            Some(Chain::Or) => "; or ",
+            Some(Chain::Then) => "; ",
+            None => "",
+        });
+        changed |= matches!(chain, Some(Chain::And | Chain::Or));
+    }
+
+    changed.then_some(translated)
+}
+
+/// Why `command` would still fail in fish after translation, if it would
+pub fn fish_incompatibility(command: &str) -> Option<&'static str> {
+    if command.contains('`') {
+        Some("uses backtick substitution, which fish does not support; use (command) instead")
+    } else if command.contains("[[") {
+        Some("uses [[ ... ]], which fish does not support; use test instead")
+    } else if command.contains("<<") {
+        Some("uses a heredoc, which fish does not support")
+    } else {
+        None
+    }
+}
+
+fn translate_fish_part(part: &str) -> Option<String> {
+    let words = shell_words(part);
+    let (first, rest) = words.split_first()?;
+
+    if first == "export" && !rest.is_empty() {
+        let sets: Vec<String> = rest
+            .iter()
+            .map(|word| match split_assignment(word) {
+                Some((name, value)) => format!("set -gx {} {}", name, fish_value(value)),
+                // `export NAME` marks an existing variable for export
+                None => format!("set -gx {} ${}", word, word),
+            })
+            .collect();
+        return Some(sets.join("; "));
+    }
+
+    let assignments = words
+        .iter()
+        .take_while(|word| split_assignment(word).is_some())
+        .count();
+    if assignments == 0 {
+        None
+    } else if assignments == words.len() {
+        let sets: Vec<String> = words
+            .iter()
+            .filter_map(|word| split_assignment(word))
+            .map(|(name, value)| format!("set -g {} {}", name, fish_value(value)))
+            .collect();
+        Some(sets.join("; "))
+    } else {
+        // `A=b cmd` only works in fish 3.1+, `env` works everywhere
+        Some(format!("env {}", words.join(" ")))
+    }
+}
+
+/// `NAME=value` with a valid variable name
+fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some((name, value))
+}
+
+fn fish_value(value: &str) -> &str {
+    if value.is_empty() {
+        "\"\""
+    } else {
+        value
+    }
+}
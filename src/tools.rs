@@ -0,0 +1,130 @@
+use std::env;
+use std::path::Path;
+
+/// Shell keywords and builtins that never live on PATH
+const SHELL_BUILTINS: &[&str] = &[
+    "cd", "export", "source", ".", "alias", "unalias", "set", "unset", "echo", "printf", "read",
+    "type", "eval", "exec", "exit", "history", "jobs", "fg", "bg", "wait", "pushd", "popd",
+    "dirs", "ulimit", "umask", "test", "[", "for", "while", "if", "case", "function", "time",
+];
+
+/// Known package names where they differ from the binary name, as (binary, brew, apt)
+const PACKAGE_NAMES: &[(&str, &str, &str)] = &[
+    ("rg", "ripgrep", "ripgrep"),
+    ("fd", "fd", "fd-find"),
+    ("bat", "bat", "bat"),
+    ("jq", "jq", "jq"),
+    ("htop", "htop", "htop"),
+    ("tree", "tree", "tree"),
+    ("fzf", "fzf", "fzf"),
+    ("http", "httpie", "httpie"),
+    ("delta", "git-delta", "git-delta"),
+    ("exa", "exa", "exa"),
+    ("eza", "eza", "eza"),
+    ("gh", "gh", "gh"),
+    ("wget", "wget", "wget"),
+];
+
+/// Modern replacements worth mentioning to the model when they are installed
+const DETECTED_TOOLS: &[&str] = &["rg", "fd", "bat", "eza", "jq", "fzf", "delta", "gh", "http"];
+
+/// Cap on the tools named in the prompt, so the hint stays one short line
+pub const MAX_PREFERRED_TOOLS: usize = 8;
+
+/// Tools to steer suggestions towards: the declared ones first, then any of
+/// `DETECTED_TOOLS` found on PATH when `detect` is set, without repeats
+pub fn preferred_tools(declared: &[String], detect: bool) -> Vec<String> {
+    let detected = DETECTED_TOOLS
+        .iter()
+        .filter(|tool| detect && is_installed(tool))
+        .map(|tool| tool.to_string());
+
+    let mut tools: Vec<String> = Vec::new();
+    for tool in declared.iter().map(|tool| tool.trim().to_string()).chain(detected) {
+        if !tool.is_empty() && !tools.contains(&tool) {
+            tools.push(tool);
+        }
+    }
+    tools.truncate(MAX_PREFERRED_TOOLS);
+    tools
+}
+
+/// The program a command line runs, skipping `sudo` and `VAR=value` prefixes
+pub fn command_program(command: &str) -> Option<&str> {
+    command
+        .split_whitespace()
+        .find(|token| *token != "sudo" && !token.contains('='))
+}
+
+/// Whether `program` can be run: a builtin, a path that exists, or an executable on PATH
+pub fn is_installed(program: &str) -> bool {
+    if SHELL_BUILTINS.contains(&program) {
+        return true;
+    }
+
+    if program.contains('/') {
+        return Path::new(program).exists();
+    }
+
+    let Some(path_var) = env::var_os("PATH") else {
+        return false;
+    };
+
+    env::split_paths(&path_var).any(|dir| {
+        let candidate = dir.join(program);
+        if cfg!(target_os = "windows") {
+            candidate.with_extension("exe").is_file() || candidate.is_file()
+        } else {
+            is_executable(&candidate)
+        }
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Stricter check than the suggestion heuristics: the line parses as shell words and
+/// its program is a plain name that can actually be run here
+pub fn is_runnable(command: &str) -> bool {
+    if shlex::split(command).is_none() {
+        return false;
+    }
+
+    command_program(command).is_some_and(|program| {
+        program
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./+".contains(c))
+            && is_installed(program)
+    })
+}
+
+/// Suggest how to install a missing program with the local package manager
+pub fn install_hint(program: &str) -> Option<String> {
+    let (brew_name, apt_name) = PACKAGE_NAMES
+        .iter()
+        .find(|(binary, _, _)| *binary == program)
+        .map(|(_, brew, apt)| (*brew, *apt))
+        .unwrap_or((program, program));
+
+    if is_installed("brew") {
+        Some(format!("brew install {}", brew_name))
+    } else if is_installed("apt-get") {
+        Some(format!("sudo apt install {}", apt_name))
+    } else if is_installed("dnf") {
+        Some(format!("sudo dnf install {}", apt_name))
+    } else if is_installed("pacman") {
+        Some(format!("sudo pacman -S {}", apt_name))
+    } else {
+        None
+    }
+}
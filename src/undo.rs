@@ -0,0 +1,112 @@
+use std::path::Path;
+
+/// How to reverse a command, worked out before it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Undo {
+    /// Running this command restores the previous state
+    Command(String),
+    /// The command cannot be reversed safely
+    Unsupported(String),
+}
+
+/// Work out the inverse of `command` from the current state of the filesystem.
+/// Only simple `mkdir`, `touch`, `cp` and `mv` invocations are supported; anything
+/// that might overwrite or delete data is reported as unsupported rather than guessed.
+pub fn plan_undo(command: &str, cwd: &Path) -> Undo {
+    if ["|", ";", "&", ">", "<", "$(", "`"]
+        .iter()
+        .any(|op| command.contains(op))
+    {
+        return Undo::Unsupported("pipes, redirects and chained commands cannot be undone".to_string());
+    }
+
+    let Some(args) = shlex::split(command) else {
+        return Undo::Unsupported("the command could not be parsed".to_string());
+    };
+    let Some((program, rest)) = args.split_first() else {
+        return Undo::Unsupported("empty command".to_string());
+    };
+
+    // Verbose output is the only flag that doesn't change what gets created
+    if rest.iter().any(|arg| arg.starts_with('-') && arg != "-v") {
+        return Undo::Unsupported(format!("`{}` with these options cannot be undone", program));
+    }
+    let operands: Vec<&str> = rest
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "-v")
+        .collect();
+    if operands.is_empty() {
+        return Undo::Unsupported(format!("`{}` without arguments changes nothing", program));
+    }
+    // The shell expands these, so the paths checked and quoted here wouldn't be the real ones
+    if operands.iter().any(|arg| arg.starts_with('~') || arg.contains('$')) {
+        return Undo::Unsupported("paths with `~` or variables cannot be undone".to_string());
+    }
+
+    let exists = |p: &str| cwd.join(p).symlink_metadata().is_ok();
+
+    match program.as_str() {
+        "mkdir" => {
+            if operands.iter().any(|dir| exists(dir)) {
+                return Undo::Unsupported("some of the directories already exist".to_string());
+            }
+            // Remove in reverse order so nested directories go first
+            let dirs: Vec<&str> = operands.iter().rev().copied().collect();
+            Undo::Command(format!("rmdir {}", quote_all(&dirs)))
+        }
+        "touch" => {
+            if operands.iter().any(|file| exists(file)) {
+                return Undo::Unsupported(
+                    "touch only updates timestamps of files that already exist".to_string(),
+                );
+            }
+            Undo::Command(format!("rm {}", quote_all(&operands)))
+        }
+        "cp" | "mv" => {
+            let [source, target] = operands[..] else {
+                return Undo::Unsupported(format!(
+                    "only `{} <source> <target>` with a single source can be undone",
+                    program
+                ));
+            };
+            if !exists(source) {
+                return Undo::Unsupported(format!("{} does not exist", source));
+            }
+
+            // Into an existing directory the file keeps its name
+            let destination = if cwd.join(target).is_dir() {
+                let Some(name) = Path::new(source).file_name() else {
+                    return Undo::Unsupported(format!("cannot tell where {} would go", source));
+                };
+                Path::new(target).join(name).to_string_lossy().into_owned()
+            } else {
+                target.to_string()
+            };
+            if exists(&destination) {
+                return Undo::Unsupported(format!("{} would be overwritten", destination));
+            }
+
+            if program == "mv" {
+                Undo::Command(format!("mv {}", quote_all(&[&destination, source])))
+            } else if cwd.join(source).is_dir() {
+                Undo::Unsupported("copies of directories need `cp -r`".to_string())
+            } else {
+                Undo::Command(format!("rm {}", quote_all(&[&destination])))
+            }
+        }
+        _ => Undo::Unsupported(format!("shy doesn't know how to reverse `{}`", program)),
+    }
+}
+
+fn quote_all(paths: &[&str]) -> String {
+    let quoted: Vec<String> = paths
+        .iter()
+        .map(|p| {
+            shlex::try_quote(p)
+                .map(|q| q.into_owned())
+                .unwrap_or_else(|_| p.to_string())
+        })
+        .collect();
+    format!("-- {}", quoted.join(" "))
+}